@@ -0,0 +1,44 @@
+//! Measures how many instructions per second the emulator executes, with and without the
+//! decode cache. Run with `cargo run --release --example throughput`. The opcode handlers are
+//! compared with the match dispatch they replaced by the ignored `dispatch_throughput` test
+
+use std::time::Instant;
+
+use y_cpu::{assembler::assemble, CPU};
+
+const STEPS: usize = 20_000_000;
+
+fn main() {
+    // An endless loop mixing arithmetic, logic, a comparison and a jump
+    let source = "
+        inc m0
+        add m1, m0
+        xor m2, m1
+        compeq m3, m5
+        halt
+        jmp m4
+    ";
+    let image = assemble(source).unwrap();
+
+    for cached in [false, true] {
+        let mut cpu = CPU::new(image, Vec::new()).unwrap();
+        cpu.set_decode_cache(cached);
+        cpu.set_data_bank(0, {
+            let mut data = [0; 64];
+            data[5] = 1;
+            data
+        });
+
+        let start = Instant::now();
+        let (halted, steps) = cpu.run(STEPS);
+        let elapsed = start.elapsed();
+        println!(
+            "decode cache {}: {:?} after {} steps in {:?}, {:.1} million instructions per second",
+            if cached { "on" } else { "off" },
+            halted,
+            steps,
+            elapsed,
+            steps as f64 / elapsed.as_secs_f64() / 1e6
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use alloc::{collections::BTreeSet, vec::Vec};
+use core::ops::Range;
+
+use crate::{disassemble, Instruction};
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProgramReport {
+    /// Addresses of the unconditional jumps to themselves, which loop forever
+    pub self_jumps: Vec<u8>,
+    /// Instructions after an unconditional jump, halt or return that no jump targets.
+    /// Trailing zeroes are not reported
+    pub unreachable: Vec<Range<u8>>,
+}
+
+/// What `reachable_addrs` found by following a program
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Reachability {
+    /// Addresses of the instructions reachable from address 0
+    pub reachable: BTreeSet<u8>,
+    /// Addresses of the reachable jumps, calls and comparisons whose target or skip count is
+    /// not known, from which more code may be reachable
+    pub dynamic: BTreeSet<u8>,
+}
+
+/// Looks for obvious mistakes in a program without running it. Jump targets and skip counts
+/// are only known when they are stored in instruction memory, read from the program counter
+/// or loaded by a LoadImm right before the instruction. Past a comparison whose skip count is
+/// not known, any instruction may be skipped or skipped to, so none is reported. Interrupt
+/// handlers are not known at all, so code only reached through them is reported as
+/// unreachable
+pub fn analyze(image: &[u8; 127]) -> ProgramReport {
+    let program = disassemble(image);
+    let mut targets: BTreeSet<u8> = program
+        .iter()
+        .filter_map(|&(pc, inst)| jump_target(image, pc, inst))
+        .collect();
+    // Instructions a comparison may skip
+    let mut skippable = BTreeSet::new();
+    let mut unknown_skip = None;
+    for &(pc, inst) in program.iter().filter(|(_, inst)| inst.is_comparison()) {
+        let next = pc + inst.size();
+        match skip_count(image, pc, inst) {
+            Some(count) => {
+                let landing = next as u16 + count as u16 * inst.size() as u16;
+                skippable.extend((next as u16..landing.min(256)).map(|addr| addr as u8));
+                targets.extend(u8::try_from(landing));
+            }
+            None => {
+                unknown_skip.get_or_insert(pc);
+            }
+        }
+    }
+    let past_unknown_skip = |pc: u8| unknown_skip.is_some_and(|comparison| pc > comparison);
+    let guarded = |pc: u8| skippable.contains(&pc) || past_unknown_skip(pc);
+
+    let mut report = ProgramReport::default();
+    let mut dead_since = None;
+    let mut last_used = 0;
+    for &(pc, inst) in &program {
+        if targets.contains(&pc) || past_unknown_skip(pc) {
+            match dead_since.take() {
+                Some(start) if start < pc => report.unreachable.push(start..pc),
+                _ => (),
+            }
+        }
+        let end = pc + inst.size();
+        if image[pc as usize..end as usize]
+            .iter()
+            .any(|&byte| byte != 0)
+        {
+            last_used = end;
+        }
+        if jump_target(image, pc, inst) == Some(pc)
+            && !matches!(inst, Instruction::Call(..))
+            && !guarded(pc)
+        {
+            report.self_jumps.push(pc);
+        }
+        let ends_flow = matches!(
+            inst,
+            Instruction::Halt(..)
+                | Instruction::Jmp(..)
+                | Instruction::JmpRel(..)
+                | Instruction::Ret(..)
+        );
+        if ends_flow && !guarded(pc) && dead_since.is_none() {
+            dead_since = Some(end);
+        }
+    }
+    if let Some(start) = dead_since {
+        if last_used > start {
+            report.unreachable.push(start..last_used);
+        }
+    }
+    report
+}
+
+/// Follows the program from address 0 through jumps, calls and comparison skips. Targets
+/// are known the same way as in `analyze`: the instructions going anywhere else are reported
+/// as dynamic and not followed, and neither are interrupt handlers and writes to the program
+/// counter
+pub fn reachable_addrs(image: &[u8; 127]) -> Reachability {
+    let mut found = Reachability::default();
+    let mut worklist = Vec::from([0]);
+    while let Some(pc) = worklist.pop() {
+        let Some(inst) = decode(image, pc) else {
+            continue;
+        };
+        if !found.reachable.insert(pc) {
+            continue;
+        }
+        let next = pc.wrapping_add(inst.size());
+        let mut follow = |target: Option<u8>| match target {
+            Some(target) => worklist.push(target),
+            None => {
+                found.dynamic.insert(pc);
+            }
+        };
+        match inst {
+            Instruction::Halt(..) | Instruction::Ret(..) => (),
+            Instruction::Jmp(..) | Instruction::JmpRel(..) => follow(jump_target(image, pc, inst)),
+            Instruction::Call(..) => {
+                follow(jump_target(image, pc, inst));
+                worklist.push(next);
+            }
+            _ if inst.is_comparison() => {
+                let landing = skip_count(image, pc, inst)
+                    .map(|count| next as u16 + count as u16 * inst.size() as u16);
+                match landing {
+                    // Skipping past the end halts
+                    Some(landing) => worklist.extend(u8::try_from(landing)),
+                    None => follow(None),
+                }
+                worklist.push(next);
+            }
+            _ => worklist.push(next),
+        }
+    }
+    found
+}
+
+fn decode(image: &[u8; 127], pc: u8) -> Option<Instruction> {
+    let pc = pc as usize;
+    (pc + Instruction::MAX_SIZE as usize <= image.len())
+        .then(|| Instruction::from_3bytes([image[pc], image[pc + 1], image[pc + 2]]))
+}
+
+/// The value stored at `addr` when executing the instruction at `pc`, if it is known
+fn known_value(image: &[u8; 127], pc: u8, addr: u8) -> Option<u8> {
+    // Address 0 is the program counter, which points at the instruction itself
+    if addr == 0 {
+        return Some(pc);
+    }
+    let previous = pc
+        .checked_sub(Instruction::MAX_SIZE)
+        .and_then(|previous| decode(image, previous));
+    match previous {
+        Some(Instruction::LoadImm(_, _, _, _, dst, value)) if dst == addr => Some(value),
+        _ => (1..=126).contains(&addr).then(|| image[addr as usize]),
+    }
+}
+
+fn jump_target(image: &[u8; 127], pc: u8, inst: Instruction) -> Option<u8> {
+    match inst {
+        Instruction::Jmp(_, _, _, _, addr) | Instruction::Call(_, _, _, _, addr) => {
+            known_value(image, pc, addr)
+        }
+        Instruction::JmpRel(_, _, _, _, addr) => known_value(image, pc, addr)
+            .map(|offset| pc.wrapping_add(inst.size()).wrapping_add(offset)),
+        _ => None,
+    }
+}
+
+/// How many instructions the comparison at `pc` skips when it does not hold, if it is known.
+/// With its skip count bit set, the count is stored right after its second argument
+fn skip_count(image: &[u8; 127], pc: u8, inst: Instruction) -> Option<u8> {
+    if !inst.flags().store_debug_info {
+        return Some(1);
+    }
+    let (_, arg2) = inst.operands();
+    known_value(image, pc, arg2?.wrapping_add(1))
+}
@@ -0,0 +1,412 @@
+use alloc::{borrow::ToOwned, collections::BTreeMap, string::String, vec::Vec};
+
+use crate::{Instruction, DATA_PTR_ADDR, DEVICE_BASE, FLAGS_ADDR, INST_PTR_ADDR};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AssembleError {
+    UnknownMnemonic {
+        line: usize,
+        mnemonic: String,
+    },
+    UnknownSuffix {
+        line: usize,
+        suffix: String,
+    },
+    /// `.skip` on anything but a comparison, or `.debug` on a comparison, which uses the same
+    /// bit as its skip count bit
+    SuffixNotAllowed {
+        line: usize,
+        suffix: String,
+    },
+    WrongOperandCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    InvalidOperand {
+        line: usize,
+        operand: String,
+    },
+    RegisterOutOfRange {
+        line: usize,
+        operand: String,
+    },
+    ProgramTooLarge {
+        line: usize,
+    },
+    UndefinedLabel {
+        line: usize,
+        label: String,
+    },
+    DuplicateLabel {
+        line: usize,
+        label: String,
+    },
+    /// Bytes emitted over the ones of an earlier line, once `.org` moved back
+    Overlap {
+        line: usize,
+        earlier_line: usize,
+    },
+}
+
+/// Byte address of every label, by name
+type Labels<'a> = BTreeMap<&'a str, u8>;
+
+/// The bytes jumps to a label read their target from, put in the highest bytes of the image
+/// that nothing else is emitted to
+struct Constants {
+    /// Whether each byte of the image is taken
+    used: [bool; 127],
+    /// Address of the byte holding each value
+    addresses: BTreeMap<u8, u8>,
+}
+
+impl Constants {
+    /// The address of a byte holding `value`, `None` if the image is full. Address 0 is
+    /// never used since reading it gives the program counter
+    fn address_of(&mut self, value: u8) -> Option<u8> {
+        if let Some(&address) = self.addresses.get(&value) {
+            return Some(address);
+        }
+        let address = (1..self.used.len()).rev().find(|&addr| !self.used[addr])?;
+        self.used[address] = true;
+        self.addresses.insert(value, address as u8);
+        Some(address as u8)
+    }
+}
+
+/// What a line emits into the image
+enum Item<'a> {
+    Instruction(&'a str),
+    Bytes(Vec<&'a str>),
+}
+
+/// Assembles a program, one instruction per line. A line like `loop:` defines a label, which
+/// can then be used in place of an address or a literal to get the address of the
+/// instruction following it. Jumps read their target from the address they are given, so
+/// `jmp loop` reads it from a byte holding the address of `loop`, which is put in the
+/// highest free byte of the image. `.byte 0x2A, 0x10` emits literal bytes instead of an
+/// instruction, and `.org 96` moves the position the next line is emitted at, which cannot
+/// emit over the bytes of an earlier line
+pub fn assemble(source: &str) -> Result<[u8; 127], AssembleError> {
+    // First pass: find where every label points to
+    let mut labels = Labels::new();
+    let mut items = Vec::new();
+    let mut pos = 0;
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let text = text.split("//").next().unwrap_or("").trim();
+        match text.strip_suffix(':') {
+            _ if text.is_empty() => (),
+            Some(label) if is_label(label) => {
+                if labels.insert(label, pos as u8).is_some() {
+                    return Err(AssembleError::DuplicateLabel {
+                        line,
+                        label: label.to_owned(),
+                    });
+                }
+            }
+            _ => match text.split_once(char::is_whitespace).unwrap_or((text, "")) {
+                (".org", rest) => {
+                    let operands = split_operands(rest);
+                    let [origin] = operands[..] else {
+                        return Err(AssembleError::WrongOperandCount {
+                            line,
+                            expected: 1,
+                            found: operands.len(),
+                        });
+                    };
+                    pos = parse_immediate(line, origin)? as usize;
+                    if pos > 127 {
+                        return Err(AssembleError::ProgramTooLarge { line });
+                    }
+                }
+                (".byte", rest) => {
+                    let operands = split_operands(rest);
+                    if operands.is_empty() {
+                        return Err(AssembleError::WrongOperandCount {
+                            line,
+                            expected: 1,
+                            found: 0,
+                        });
+                    }
+                    let len = operands.len();
+                    items.push((line, pos, Item::Bytes(operands)));
+                    pos += len;
+                }
+                (directive, _) if directive.starts_with('.') => {
+                    return Err(AssembleError::UnknownMnemonic {
+                        line,
+                        mnemonic: directive.to_owned(),
+                    })
+                }
+                _ => {
+                    // Labels are not all known yet, but they do not change the size
+                    let size = assemble_line(line, pos, text, None)?.size();
+                    items.push((line, pos, Item::Instruction(text)));
+                    pos += size as usize;
+                }
+            },
+        }
+    }
+
+    // The line emitting each byte
+    let mut emitted_by = [None; 127];
+    for &(line, pos, ref item) in &items {
+        let len = match item {
+            Item::Instruction(_) => Instruction::MAX_SIZE as usize,
+            Item::Bytes(operands) => operands.len(),
+        };
+        for emitter in emitted_by.iter_mut().skip(pos).take(len) {
+            if let Some(earlier_line) = *emitter {
+                return Err(AssembleError::Overlap { line, earlier_line });
+            }
+            *emitter = Some(line);
+        }
+    }
+    let mut constants = Constants {
+        used: emitted_by.map(|emitter| emitter.is_some()),
+        addresses: BTreeMap::new(),
+    };
+
+    let mut image = [0; 127];
+    for (line, pos, item) in items {
+        let bytes = match item {
+            Item::Instruction(text) => {
+                assemble_line(line, pos, text, Some((&labels, &mut constants)))?
+                    .to_3bytes()
+                    .to_vec()
+            }
+            Item::Bytes(operands) => operands
+                .iter()
+                .map(|op| resolve(line, op, Some(&labels), parse_immediate))
+                .collect::<Result<_, _>>()?,
+        };
+        if pos + bytes.len() > image.len() {
+            return Err(AssembleError::ProgramTooLarge { line });
+        }
+        image[pos..pos + bytes.len()].copy_from_slice(&bytes);
+    }
+    for (value, address) in constants.addresses {
+        image[address as usize] = value;
+    }
+    Ok(image)
+}
+
+/// Assembles the instruction at `pos`, with every label at 0 when `labels` are not known yet
+fn assemble_line(
+    line: usize,
+    pos: usize,
+    text: &str,
+    labels: Option<(&Labels, &mut Constants)>,
+) -> Result<Instruction, AssembleError> {
+    let (head, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let mut parts = head.split('.');
+    let mnemonic = parts.next().unwrap_or("");
+
+    let (mut h, mut s1, mut s2) = (false, false, false);
+    let (mut debug, mut skip) = (false, false);
+    for suffix in parts {
+        match suffix {
+            "s1" => s1 = true,
+            "s2" => s2 = true,
+            "halt" => h = true,
+            "debug" => debug = true,
+            "skip" => skip = true,
+            _ => {
+                return Err(AssembleError::UnknownSuffix {
+                    line,
+                    suffix: suffix.to_owned(),
+                })
+            }
+        }
+    }
+
+    let d = debug || skip;
+
+    let operands = split_operands(rest);
+    let (labels, mut constants) = labels.unzip();
+    let addr = |i: usize| {
+        operands
+            .get(i)
+            .map_or(Ok(0), |op| resolve(line, op, labels, parse_operand))
+    };
+    let imm = |i: usize| {
+        operands
+            .get(i)
+            .map_or(Ok(0), |op| resolve(line, op, labels, parse_immediate))
+    };
+    // JmpRel goes to a label through its offset from the following instruction
+    let relative = mnemonic.eq_ignore_ascii_case("jmprel");
+    let mut target = |i: usize| match operands.get(i) {
+        Some(op)
+            if is_label(op)
+                && matches!(
+                    parse_operand(line, op),
+                    Err(AssembleError::InvalidOperand { .. })
+                ) =>
+        {
+            let label = resolve(line, op, labels, parse_immediate)?;
+            let value = if relative {
+                label.wrapping_sub((pos + Instruction::MAX_SIZE as usize) as u8)
+            } else {
+                label
+            };
+            match constants.as_deref_mut() {
+                Some(constants) => constants
+                    .address_of(value)
+                    .ok_or(AssembleError::ProgramTooLarge { line }),
+                None => Ok(0),
+            }
+        }
+        _ => addr(i),
+    };
+
+    let (expected, inst) = match mnemonic.to_ascii_uppercase().as_str() {
+        "NOOP" => (0, Instruction::NoOp(h, d, s1, s2)),
+        "HALT" => (0, Instruction::Halt(h, d, s1, s2)),
+        "AND" => (2, Instruction::And(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "NAND" => (2, Instruction::Nand(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "BITTEST" => (2, Instruction::BitTest(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "OR" => (2, Instruction::Or(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "XOR" => (2, Instruction::Xor(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "NOR" => (2, Instruction::Nor(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "NOT" => (1, Instruction::Not(h, d, s1, s2, addr(0)?)),
+        "ADD" => (2, Instruction::Add(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "ADDC" => (2, Instruction::AddC(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "SUB" => (2, Instruction::Sub(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "MUL" => (2, Instruction::Mul(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "DIV" => (2, Instruction::Div(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "MOD" => (2, Instruction::Mod(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "SL" => (2, Instruction::SL(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "SR" => (2, Instruction::SR(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "RL" => (2, Instruction::RL(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "RR" => (2, Instruction::RR(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "SRA" => (1, Instruction::SRA(h, d, s1, s2, addr(0)?)),
+        "INC" => (1, Instruction::Inc(h, d, s1, s2, addr(0)?)),
+        "DEC" => (1, Instruction::Dec(h, d, s1, s2, addr(0)?)),
+        "NEG" => (1, Instruction::Neg(h, d, s1, s2, addr(0)?)),
+        "JMP" => (1, Instruction::Jmp(h, d, s1, s2, target(0)?)),
+        "JMPREL" => (1, Instruction::JmpRel(h, d, s1, s2, target(0)?)),
+        "CALL" => (1, Instruction::Call(h, d, s1, s2, target(0)?)),
+        "RET" => (0, Instruction::Ret(h, d, s1, s2)),
+        "PUSHCTX" => (0, Instruction::PushCtx(h, d, s1, s2)),
+        "POPCTX" => (0, Instruction::PopCtx(h, d, s1, s2)),
+        "CLC" => (0, Instruction::Clc(h, d, s1, s2)),
+        "SEC" => (0, Instruction::Sec(h, d, s1, s2)),
+        "POPCOUNT" => (1, Instruction::PopCount(h, d, s1, s2, addr(0)?)),
+        "CLZ" => (1, Instruction::Clz(h, d, s1, s2, addr(0)?)),
+        "BANKINST" => (1, Instruction::BankInst(h, d, s1, s2, addr(0)?)),
+        "BANKDATA" => (1, Instruction::BankData(h, d, s1, s2, addr(0)?)),
+        "COPY" => (2, Instruction::Copy(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "LOADIMM" => (2, Instruction::LoadImm(h, d, s1, s2, addr(0)?, imm(1)?)),
+        "SWAP" => (2, Instruction::Swap(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "BLOCKCOPY" => (2, Instruction::BlockCopy(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "COMPEQ" => (2, Instruction::CompEq(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "COMPNE" => (2, Instruction::CompNe(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "CMOVZ" => (2, Instruction::CMovZ(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "CMOVNZ" => (2, Instruction::CMovNz(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "COMPGT" => (2, Instruction::CompGt(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "COMPLT" => (2, Instruction::CompLt(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "COMPGE" => (2, Instruction::CompGe(h, d, s1, s2, addr(0)?, addr(1)?)),
+        "COMPLE" => (2, Instruction::CompLe(h, d, s1, s2, addr(0)?, addr(1)?)),
+        _ => {
+            return Err(AssembleError::UnknownMnemonic {
+                line,
+                mnemonic: mnemonic.to_owned(),
+            })
+        }
+    };
+    if operands.len() != expected {
+        return Err(AssembleError::WrongOperandCount {
+            line,
+            expected,
+            found: operands.len(),
+        });
+    }
+    let misplaced = if inst.is_comparison() {
+        debug.then_some("debug")
+    } else {
+        skip.then_some("skip")
+    };
+    if let Some(suffix) = misplaced {
+        return Err(AssembleError::SuffixNotAllowed {
+            line,
+            suffix: suffix.to_owned(),
+        });
+    }
+    Ok(inst)
+}
+
+fn split_operands(rest: &str) -> Vec<&str> {
+    rest.split(',')
+        .map(str::trim)
+        .filter(|operand| !operand.is_empty())
+        .collect()
+}
+
+/// Parses an operand with `parse`, falling back to the address of a label
+fn resolve(
+    line: usize,
+    operand: &str,
+    labels: Option<&Labels>,
+    parse: fn(usize, &str) -> Result<u8, AssembleError>,
+) -> Result<u8, AssembleError> {
+    match (parse(line, operand), labels) {
+        (Err(AssembleError::InvalidOperand { .. }), None) if is_label(operand) => Ok(0),
+        (Err(AssembleError::InvalidOperand { .. }), Some(labels)) if is_label(operand) => labels
+            .get(operand)
+            .copied()
+            .ok_or_else(|| AssembleError::UndefinedLabel {
+                line,
+                label: operand.to_owned(),
+            }),
+        (result, _) => result,
+    }
+}
+
+fn is_label(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parses an operand written the way `Instruction`'s Display prints it
+fn parse_operand(line: usize, operand: &str) -> Result<u8, AssembleError> {
+    let (base, max, number) = match operand {
+        "ip" => return Ok(INST_PTR_ADDR),
+        "dp" => return Ok(DATA_PTR_ADDR),
+        "flags" => return Ok(FLAGS_ADDR),
+        _ if operand.starts_with("dev") => (DEVICE_BASE, 255 - DEVICE_BASE as u32, &operand[3..]),
+        _ if operand.starts_with('r') => (0, 127, &operand[1..]),
+        _ if operand.starts_with('m') => (128, 63, &operand[1..]),
+        _ => {
+            return Err(AssembleError::InvalidOperand {
+                line,
+                operand: operand.to_owned(),
+            })
+        }
+    };
+    let number: u32 = number.parse().map_err(|_| AssembleError::InvalidOperand {
+        line,
+        operand: operand.to_owned(),
+    })?;
+    if number > max {
+        return Err(AssembleError::RegisterOutOfRange {
+            line,
+            operand: operand.to_owned(),
+        });
+    }
+    Ok(base + number as u8)
+}
+
+/// Parses a literal byte, either in decimal or in hexadecimal with a `0x` prefix
+fn parse_immediate(line: usize, operand: &str) -> Result<u8, AssembleError> {
+    let parsed = match operand.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => operand.parse(),
+    };
+    parsed.map_err(|_| AssembleError::InvalidOperand {
+        line,
+        operand: operand.to_owned(),
+    })
+}
@@ -0,0 +1,144 @@
+use alloc::{boxed::Box, rc::Rc, vec::Vec};
+use core::cell::{Ref, RefCell};
+#[cfg(feature = "std")]
+use std::io::Write;
+
+use crate::Device;
+
+/// The bytes written to a `ConsoleDevice`, which can still be read once the CPU owns it
+#[derive(Clone)]
+pub struct ConsoleOutput(Rc<RefCell<Vec<u8>>>);
+
+impl ConsoleOutput {
+    pub fn bytes(&self) -> Ref<'_, [u8]> {
+        Ref::map(self.0.borrow(), Vec::as_slice)
+    }
+}
+
+/// Collects every byte written to it, and optionally forwards them to a writer
+pub struct ConsoleDevice {
+    address: u8,
+    output: ConsoleOutput,
+    #[cfg(feature = "std")]
+    writer: Option<Box<dyn Write>>,
+}
+
+impl ConsoleDevice {
+    pub fn new(address: u8) -> ConsoleDevice {
+        ConsoleDevice {
+            address,
+            output: ConsoleOutput(Rc::default()),
+            #[cfg(feature = "std")]
+            writer: None,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn with_writer(address: u8, writer: Box<dyn Write>) -> ConsoleDevice {
+        ConsoleDevice {
+            writer: Some(writer),
+            ..ConsoleDevice::new(address)
+        }
+    }
+
+    pub fn output(&self) -> Ref<'_, [u8]> {
+        self.output.bytes()
+    }
+
+    /// A handle on the output, to read it after handing the device over to the CPU
+    pub fn output_handle(&self) -> ConsoleOutput {
+        self.output.clone()
+    }
+}
+
+impl Device for ConsoleDevice {
+    fn load(&mut self, _addr: u8) -> u8 {
+        0
+    }
+
+    fn push(&mut self, _addr: u8, data: u8) {
+        self.output.0.borrow_mut().push(data);
+        #[cfg(feature = "std")]
+        if let Some(writer) = &mut self.writer {
+            let _ = writer.write_all(&[data]).and_then(|_| writer.flush());
+        }
+    }
+
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// The copy keeps the output collected so far, but does not forward to the writer, and
+    /// handles taken from this device do not see what is written to the copy
+    fn clone_box(&self) -> Box<dyn Device> {
+        Box::new(ConsoleDevice {
+            output: ConsoleOutput(Rc::new(RefCell::new(self.output.bytes().to_vec()))),
+            ..ConsoleDevice::new(self.address)
+        })
+    }
+}
+
+/// Returns a pseudo random byte on every read, writing to it reseeds the generator
+#[derive(Clone)]
+pub struct RngDevice {
+    address: u8,
+    /// Seed given at construction, restored on reset
+    initial_seed: u32,
+    state: u32,
+}
+
+impl RngDevice {
+    pub fn new(address: u8, seed: u32) -> RngDevice {
+        let mut device = RngDevice {
+            address,
+            initial_seed: seed,
+            state: 0,
+        };
+        device.seed(seed);
+        device
+    }
+
+    /// xorshift gets stuck on 0, so that seed is replaced
+    fn seed(&mut self, seed: u32) {
+        self.state = if seed == 0 { 0x2545_f491 } else { seed };
+    }
+
+    fn next(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+}
+
+impl Device for RngDevice {
+    fn load(&mut self, _addr: u8) -> u8 {
+        (self.next() >> 24) as u8
+    }
+
+    fn push(&mut self, _addr: u8, data: u8) {
+        self.seed(data as u32);
+    }
+
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn clone_box(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+
+    fn reset(&mut self) {
+        self.seed(self.initial_seed);
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        self.state.to_le_bytes().to_vec()
+    }
+
+    fn restore(&mut self, data: &[u8]) {
+        if let Ok(state) = data.try_into() {
+            self.seed(u32::from_le_bytes(state));
+        }
+    }
+}
@@ -0,0 +1,481 @@
+use crate::{extend, ErrorKind, Flags, Halted, Instruction, CPU, DATA_PTR_ADDR, INST_PTR_ADDR};
+
+/// Executes an instruction from its first byte, which holds the halt, debug and signing bits,
+/// and its two arguments. Returns how far the program counter moves, or why execution stops
+pub(crate) type OpHandler = fn(&mut CPU, u8, u8, u8) -> Result<i16, Halted>;
+
+/// The handler of every opcode, indexed by the low nibble of the first byte
+pub(crate) const HANDLERS: [OpHandler; 16] = [
+    noop, and, or, unary, add, sub, mul, div, shift, modulo, comp_ge, comp_le, copy, comp_eq,
+    comp_gt, comp_lt,
+];
+
+/// Moves on to the following instruction, every instruction is `MAX_SIZE` long for now
+const NEXT: i16 = Instruction::MAX_SIZE as i16;
+
+fn halt_on_error(first: u8) -> bool {
+    first & 0b1000_0000 == 0b1000_0000
+}
+
+/// The Store debug info bit, which comparisons use as their skip count bit
+fn skip_count(first: u8) -> bool {
+    first & 0b0100_0000 == 0b0100_0000
+}
+
+fn arg1_signed(first: u8) -> bool {
+    first & 0b0010_0000 == 0b0010_0000
+}
+
+fn arg2_signed(first: u8) -> bool {
+    first & 0b0001_0000 == 0b0001_0000
+}
+
+/// The signing bits of the opcodes which use them to select an operation
+fn function(first: u8) -> u8 {
+    (first & 0b0011_0000) >> 4
+}
+
+/// Stops with an overflow when an arithmetic result did not fit
+fn overflow(fits: bool) -> Result<i16, Halted> {
+    if fits {
+        Ok(NEXT)
+    } else {
+        Err(Halted::Errored(ErrorKind::Overflow))
+    }
+}
+
+/// Checks the operands read so far and the destinations about to be written, failing when the
+/// instruction has to stop before it has any side effect
+fn check(
+    cpu: &mut CPU,
+    first: u8,
+    destinations: impl IntoIterator<Item = u8>,
+) -> Result<(), Halted> {
+    cpu.check_access(halt_on_error(first), destinations)
+        .map_err(Halted::Errored)
+}
+
+/// Loads both arguments of an operation storing its result in `arg1`
+fn load_both(cpu: &mut CPU, first: u8, arg1: u8, arg2: u8) -> Result<(u8, u8), Halted> {
+    let data1 = cpu.load(arg1);
+    let data2 = cpu.load(arg2);
+    check(cpu, first, [arg1])?;
+    Ok((data1, data2))
+}
+
+/// Loads the argument of a unary operation storing its result in place
+fn load_one(cpu: &mut CPU, first: u8, arg1: u8) -> Result<u8, Halted> {
+    let data1 = cpu.load(arg1);
+    check(cpu, first, [arg1])?;
+    Ok(data1)
+}
+
+fn noop(_: &mut CPU, _: u8, _: u8, _: u8) -> Result<i16, Halted> {
+    Ok(NEXT)
+}
+
+/// And, Nand, BitTest and AddC
+fn and(cpu: &mut CPU, first: u8, arg1: u8, arg2: u8) -> Result<i16, Halted> {
+    let data1 = cpu.load(arg1);
+    let data2 = cpu.load(arg2);
+    // BitTest only sets the flags
+    check(cpu, first, (function(first) != 2).then_some(arg1))?;
+    match function(first) {
+        3 => {
+            let carry = cpu.flags.contains(Flags::CARRY) as i32;
+            return overflow(cpu.store_arithmetic(false, arg1, data1, data2, |a, b| a + b + carry));
+        }
+        1 => cpu.store_result(arg1, !(data1 & data2)),
+        2 => cpu.set_result_flags(data1 & data2),
+        _ => cpu.store_result(arg1, data1 & data2),
+    }
+    Ok(NEXT)
+}
+
+/// Or, Xor and Nor
+fn or(cpu: &mut CPU, first: u8, arg1: u8, arg2: u8) -> Result<i16, Halted> {
+    let (data1, data2) = load_both(cpu, first, arg1, arg2)?;
+    let result = match function(first) {
+        1 => data1 ^ data2,
+        2 => !(data1 | data2),
+        _ => data1 | data2,
+    };
+    cpu.store_result(arg1, result);
+    Ok(NEXT)
+}
+
+/// The operations on a single argument, selected by the second one
+fn unary(cpu: &mut CPU, first: u8, arg1: u8, selector: u8) -> Result<i16, Halted> {
+    match selector {
+        1 => return Err(Halted::Halted),
+        2 => {
+            let data1 = i8::from_be_bytes([load_one(cpu, first, arg1)?]);
+            cpu.push(arg1, (data1 >> 1) as u8);
+        }
+        3 => {
+            let data1 = load_one(cpu, first, arg1)?;
+            return overflow(
+                cpu.store_arithmetic(arg1_signed(first), arg1, data1, 1, |a, b| a + b),
+            );
+        }
+        4 => {
+            let data1 = load_one(cpu, first, arg1)?;
+            return overflow(
+                cpu.store_arithmetic(arg1_signed(first), arg1, data1, 1, |a, b| a - b),
+            );
+        }
+        5 => {
+            let data1 = load_one(cpu, first, arg1)?;
+            // Negating only makes sense on signed values, where -128 is the one that does not
+            // fit
+            return overflow(cpu.store_arithmetic(true, arg1, 0, data1, |a, b| a - b));
+        }
+        6 => {
+            cpu.reg_zero = cpu.load(arg1);
+            return Ok(0);
+        }
+        // The offset is relative to the following instruction
+        7 => return Ok(NEXT + extend(cpu.load(arg1), true) as i16),
+        8 => {
+            let target = cpu.load(arg1);
+            check(cpu, first, [])?;
+            if !cpu.stack_push(cpu.reg_zero.wrapping_add(NEXT as u8)) {
+                return Err(Halted::Errored(ErrorKind::StackOverflow));
+            }
+            cpu.reg_zero = target;
+            return Ok(0);
+        }
+        9 => match cpu.pop_return() {
+            Some(address) => {
+                cpu.reg_zero = address;
+                return Ok(0);
+            }
+            None => return Err(Halted::Errored(ErrorKind::StackUnderflow)),
+        },
+        10 => {
+            let data1 = load_one(cpu, first, arg1)?;
+            cpu.store_result(arg1, data1.count_ones() as u8);
+        }
+        11 => {
+            let data1 = load_one(cpu, first, arg1)?;
+            cpu.store_result(arg1, data1.leading_zeros() as u8);
+        }
+        12 => {
+            let bank = cpu.load(arg1);
+            check(cpu, first, [])?;
+            cpu.push(INST_PTR_ADDR, bank);
+        }
+        13 => {
+            let bank = cpu.load(arg1);
+            check(cpu, first, [])?;
+            cpu.push(DATA_PTR_ADDR, bank);
+        }
+        14 => {
+            if cpu.sp < 3 || cpu.sp > 64 {
+                return Err(Halted::Errored(ErrorKind::StackOverflow));
+            }
+            let context = [
+                cpu.inst_mem.pointer as u8,
+                cpu.data_mem.pointer as u8,
+                cpu.flags.bits(),
+            ];
+            for data in context {
+                cpu.stack_push(data);
+            }
+        }
+        15 => {
+            if cpu.sp > 61 {
+                return Err(Halted::Errored(ErrorKind::StackUnderflow));
+            }
+            let flags = cpu.stack_pop().unwrap_or_default();
+            let data_bank = cpu.stack_pop().unwrap_or_default();
+            let inst_bank = cpu.stack_pop().unwrap_or_default();
+            cpu.flags = Flags::from_bits(flags);
+            cpu.push(DATA_PTR_ADDR, data_bank);
+            cpu.push(INST_PTR_ADDR, inst_bank);
+        }
+        16 => cpu.flags.set(Flags::CARRY, false),
+        17 => cpu.flags.set(Flags::CARRY, true),
+        _ => {
+            let data1 = load_one(cpu, first, arg1)?;
+            cpu.store_result(arg1, !data1);
+        }
+    }
+    Ok(NEXT)
+}
+
+fn add(cpu: &mut CPU, first: u8, arg1: u8, arg2: u8) -> Result<i16, Halted> {
+    let signed = arg1_signed(first) || arg2_signed(first);
+    let (data1, data2) = load_both(cpu, first, arg1, arg2)?;
+    overflow(cpu.store_arithmetic(signed, arg1, data1, data2, |a, b| a + b))
+}
+
+fn sub(cpu: &mut CPU, first: u8, arg1: u8, arg2: u8) -> Result<i16, Halted> {
+    let signed = arg1_signed(first) || arg2_signed(first);
+    let (data1, data2) = load_both(cpu, first, arg1, arg2)?;
+    overflow(cpu.store_arithmetic(signed, arg1, data1, data2, |a, b| a - b))
+}
+
+fn mul(cpu: &mut CPU, first: u8, arg1: u8, arg2: u8) -> Result<i16, Halted> {
+    let signed = arg1_signed(first) || arg2_signed(first);
+    let (data1, data2) = load_both(cpu, first, arg1, arg2)?;
+    overflow(cpu.store_arithmetic(signed, arg1, data1, data2, |a, b| a * b))
+}
+
+fn div(cpu: &mut CPU, first: u8, arg1: u8, arg2: u8) -> Result<i16, Halted> {
+    let (sign1, sign2) = (arg1_signed(first), arg2_signed(first));
+    cpu.division(halt_on_error(first), sign1, sign2, arg1, arg2, |a, b| a / b)
+        .map_err(Halted::Errored)?;
+    Ok(NEXT)
+}
+
+fn modulo(cpu: &mut CPU, first: u8, arg1: u8, arg2: u8) -> Result<i16, Halted> {
+    let (sign1, sign2) = (arg1_signed(first), arg2_signed(first));
+    cpu.division(halt_on_error(first), sign1, sign2, arg1, arg2, |a, b| a % b)
+        .map_err(Halted::Errored)?;
+    Ok(NEXT)
+}
+
+/// SL, SR, RL and RR
+fn shift(cpu: &mut CPU, first: u8, arg1: u8, arg2: u8) -> Result<i16, Halted> {
+    let (data1, count) = load_both(cpu, first, arg1, arg2)?;
+    let count = (count & 0b111) as u32;
+    let result = match function(first) {
+        1 => data1.wrapping_shr(count),
+        2 => data1.rotate_left(count),
+        3 => data1.rotate_right(count),
+        _ => data1.wrapping_shl(count),
+    };
+    cpu.push(arg1, result);
+    Ok(NEXT)
+}
+
+/// Copy, LoadImm, Swap and BlockCopy
+fn copy(cpu: &mut CPU, first: u8, arg1: u8, arg2: u8) -> Result<i16, Halted> {
+    match function(first) {
+        1 => {
+            check(cpu, first, [arg1])?;
+            cpu.push(arg1, arg2);
+        }
+        // Swapping a cell with itself does not access it at all
+        2 if arg1 == arg2 => (),
+        2 => {
+            let data1 = cpu.load(arg1);
+            let data2 = cpu.load(arg2);
+            check(cpu, first, [arg1, arg2])?;
+            cpu.push(arg1, data2);
+            cpu.push(arg2, data1);
+        }
+        3 => cpu
+            .block_copy(halt_on_error(first), arg1, arg2)
+            .map_err(Halted::Errored)?,
+        _ => {
+            let data1 = cpu.load(arg1);
+            check(cpu, first, [arg2])?;
+            cpu.push(arg2, data1);
+        }
+    }
+    Ok(NEXT)
+}
+
+/// Moves past the following instruction, or the skip count of them, unless the comparison
+/// holds
+fn compare(cpu: &mut CPU, first: u8, arg2: u8, holds: bool) -> Result<i16, Halted> {
+    if holds {
+        Ok(NEXT)
+    } else {
+        Ok(cpu.comparison_skip(skip_count(first), arg2))
+    }
+}
+
+/// CompEq, CompNe, CMovZ and CMovNz
+fn comp_eq(cpu: &mut CPU, first: u8, arg1: u8, arg2: u8) -> Result<i16, Halted> {
+    match function(first) {
+        1 => {
+            let holds = cpu.load(arg1) != cpu.load(arg2);
+            compare(cpu, first, arg2, holds)
+        }
+        function @ (2 | 3) => {
+            if cpu.zero() == (function == 2) {
+                let data1 = cpu.load(arg1);
+                check(cpu, first, [arg2])?;
+                cpu.push(arg2, data1);
+            }
+            Ok(NEXT)
+        }
+        _ => {
+            let holds = cpu.load(arg1) == cpu.load(arg2);
+            compare(cpu, first, arg2, holds)
+        }
+    }
+}
+
+/// Both arguments with their signedness applied, for the ordering comparisons
+fn operands(cpu: &mut CPU, first: u8, arg1: u8, arg2: u8) -> (i32, i32) {
+    let data1 = extend(cpu.load(arg1), arg1_signed(first));
+    let data2 = extend(cpu.load(arg2), arg2_signed(first));
+    (data1, data2)
+}
+
+fn comp_gt(cpu: &mut CPU, first: u8, arg1: u8, arg2: u8) -> Result<i16, Halted> {
+    let (data1, data2) = operands(cpu, first, arg1, arg2);
+    compare(cpu, first, arg2, data1 > data2)
+}
+
+fn comp_lt(cpu: &mut CPU, first: u8, arg1: u8, arg2: u8) -> Result<i16, Halted> {
+    let (data1, data2) = operands(cpu, first, arg1, arg2);
+    compare(cpu, first, arg2, data1 < data2)
+}
+
+fn comp_ge(cpu: &mut CPU, first: u8, arg1: u8, arg2: u8) -> Result<i16, Halted> {
+    let (data1, data2) = operands(cpu, first, arg1, arg2);
+    compare(cpu, first, arg2, data1 >= data2)
+}
+
+fn comp_le(cpu: &mut CPU, first: u8, arg1: u8, arg2: u8) -> Result<i16, Halted> {
+    let (data1, data2) = operands(cpu, first, arg1, arg2);
+    compare(cpu, first, arg2, data1 <= data2)
+}
+
+#[cfg(test)]
+mod reference;
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use alloc::vec::Vec;
+    use std::{println, time::Instant};
+
+    use super::reference;
+    use crate::{
+        assembler::assemble, ArithMode, CpuError, DebugRecord, Flags, Halted, Instruction, CPU,
+        DATA_PTR_ADDR, FLAGS_ADDR,
+    };
+
+    /// Everything an instruction can change, along with how it stopped
+    #[derive(Debug, PartialEq)]
+    struct State {
+        halted: Halted,
+        pc: u16,
+        flags: u8,
+        sp: u8,
+        banks: (usize, usize),
+        inst_mem: [u8; 127],
+        data_mem: [u8; 64],
+        debug_log: Vec<DebugRecord>,
+        access_fault: Option<CpuError>,
+        in_interrupt: bool,
+    }
+
+    fn state(cpu: &CPU, halted: Halted) -> State {
+        State {
+            halted,
+            pc: cpu.pc(),
+            flags: cpu.flags.bits(),
+            sp: cpu.sp,
+            banks: (cpu.inst_mem.pointer, cpu.data_mem.pointer),
+            inst_mem: cpu.inst_mem.content[0],
+            data_mem: cpu.data_mem.content[0],
+            debug_log: cpu.debug_log.clone(),
+            access_fault: cpu.access_fault,
+            in_interrupt: cpu.in_interrupt(),
+        }
+    }
+
+    /// A CPU about to run `bytes` with data memory, the stack and an interrupt handler set up
+    /// so that every instruction has something to work on
+    fn cpu(bytes: [u8; 3], mode: ArithMode, flags: Flags) -> CPU {
+        let mut program = [0; 127];
+        program[..3].copy_from_slice(&bytes);
+        let mut cpu = CPU::new(program, Vec::new()).unwrap();
+        for (i, cell) in cpu.data_mem.content[0].iter_mut().enumerate() {
+            *cell = (i as u8).wrapping_mul(37).wrapping_add(11);
+        }
+        cpu.data_mem.content[0][5] = 0;
+        cpu.data_mem.content[0][60] = 4;
+        cpu.sp = 60;
+        cpu.handler_sp = Some(61);
+        cpu.arith_mode = mode;
+        cpu.flags = flags;
+        cpu
+    }
+
+    /// Runs every first byte through both dispatches, on a few arguments, arithmetic modes
+    /// and flags, and compares the resulting states side by side
+    #[test]
+    fn every_opcode_matches_reference() {
+        let args = [0, 128, 129, 130, 133, 5, DATA_PTR_ADDR, FLAGS_ADDR, 200];
+        let selectors: Vec<u8> = (0..=17).chain([200]).collect();
+        for first in 0..=255u8 {
+            let arg2s = if first & 0b1111 == 3 {
+                &selectors[..]
+            } else {
+                &args[..]
+            };
+            for (&arg1, &arg2) in args.iter().flat_map(|a| arg2s.iter().map(move |b| (a, b))) {
+                for mode in [
+                    ArithMode::Wrapping,
+                    ArithMode::Trapping,
+                    ArithMode::Saturating,
+                ] {
+                    for flags in [Flags::empty(), Flags::from_bits(0b0011)] {
+                        let bytes = [first, arg1, arg2];
+                        let inst = Instruction::from_3bytes(bytes);
+                        let mut handlers = cpu(bytes, mode, flags);
+                        let halted = handlers.process(inst);
+                        let mut matched = cpu(bytes, mode, flags);
+                        let reference_halted =
+                            matched.process_with(inst, |cpu, _| reference::execute(cpu, inst));
+                        assert_eq!(
+                            state(&handlers, halted),
+                            state(&matched, reference_halted),
+                            "{inst} ({bytes:02x?}) in {mode:?} mode with flags {flags:?}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Instructions per second of both dispatches, without the devices, hooks and decoding
+    /// the ticks add. Run with
+    /// `cargo test --release -p y_cpu dispatch_throughput -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn dispatch_throughput() {
+        const STEPS: usize = 20_000_000;
+        // An endless loop mixing arithmetic, logic, a comparison and a jump
+        let source = "
+            inc m0
+            add m1, m0
+            xor m2, m1
+            compeq m3, m5
+            halt
+            jmp m4
+        ";
+        let image = assemble(source).unwrap();
+        let program: Vec<Instruction> = crate::disassemble(&image)
+            .into_iter()
+            .map(|(_, inst)| inst)
+            .collect();
+        let measure = |name: &str, execute: &dyn Fn(&mut CPU, Instruction) -> Halted| {
+            let mut cpu = CPU::new(image, Vec::new()).unwrap();
+            cpu.data_mem[5] = 1;
+            let start = Instant::now();
+            for _ in 0..STEPS {
+                let inst = program[cpu.reg_zero as usize / Instruction::MAX_SIZE as usize];
+                assert_eq!(execute(&mut cpu, inst), Halted::Running);
+            }
+            let elapsed = start.elapsed();
+            println!(
+                "{name}: {STEPS} steps in {elapsed:?}, {:.1} million instructions per second",
+                STEPS as f64 / elapsed.as_secs_f64() / 1e6
+            );
+        };
+        measure("opcode handlers", &|cpu, inst| cpu.process(inst));
+        measure("match", &|cpu, inst| {
+            cpu.process_with(inst, |cpu, _| reference::execute(cpu, inst))
+        });
+    }
+}
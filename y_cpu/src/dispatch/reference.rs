@@ -0,0 +1,299 @@
+//! The match over decoded instructions which the opcode handlers replaced, kept to check that
+//! both behave the same and to compare how fast they are
+
+use crate::{extend, ErrorKind, Flags, Halted, Instruction, CPU, DATA_PTR_ADDR, INST_PTR_ADDR};
+
+/// Runs an instruction, then moves the program counter
+pub(super) fn execute(cpu: &mut CPU, inst: Instruction) -> Halted {
+    let advance = match run(cpu, inst) {
+        Ok(advance) => advance,
+        Err(halted) => return halted,
+    };
+    match cpu.flat_mem {
+        Some(_) => cpu.set_pc(cpu.pc().wrapping_add_signed(advance)),
+        None => cpu.reg_zero = cpu.reg_zero.wrapping_add(advance as u8),
+    }
+    Halted::Running
+}
+
+/// Returns how far the program counter moves, or why execution stops
+fn run(cpu: &mut CPU, inst: Instruction) -> Result<i16, Halted> {
+    let halt_on_error = inst.flags().halt_on_error;
+    let check = |cpu: &mut CPU, destinations: &[u8]| {
+        cpu.check_access(halt_on_error, destinations.iter().copied())
+            .map_err(Halted::Errored)
+    };
+    let overflow = |fits: bool| {
+        if fits {
+            Ok(())
+        } else {
+            Err(Halted::Errored(ErrorKind::Overflow))
+        }
+    };
+    // How far the program counter moves once the instruction is done
+    let mut advance = inst.size() as i16;
+    match inst {
+        Instruction::NoOp(_, _, _, _) => (),
+        Instruction::Halt(_, _, _, _) => return Err(Halted::Halted),
+        Instruction::And(_, _, _, _, arg1, arg2) => {
+            let data1 = cpu.load(arg1);
+            let data2 = cpu.load(arg2);
+            check(cpu, &[arg1])?;
+            cpu.store_result(arg1, data1 & data2);
+        }
+        Instruction::Nand(_, _, _, _, arg1, arg2) => {
+            let data1 = cpu.load(arg1);
+            let data2 = cpu.load(arg2);
+            check(cpu, &[arg1])?;
+            cpu.store_result(arg1, !(data1 & data2));
+        }
+        Instruction::BitTest(_, _, _, _, arg1, arg2) => {
+            let data1 = cpu.load(arg1);
+            let data2 = cpu.load(arg2);
+            check(cpu, &[])?;
+            cpu.set_result_flags(data1 & data2);
+        }
+        Instruction::Or(_, _, _, _, arg1, arg2) => {
+            let data1 = cpu.load(arg1);
+            let data2 = cpu.load(arg2);
+            check(cpu, &[arg1])?;
+            cpu.store_result(arg1, data1 | data2);
+        }
+        Instruction::Xor(_, _, _, _, arg1, arg2) => {
+            let data1 = cpu.load(arg1);
+            let data2 = cpu.load(arg2);
+            check(cpu, &[arg1])?;
+            cpu.store_result(arg1, data1 ^ data2);
+        }
+        Instruction::Nor(_, _, _, _, arg1, arg2) => {
+            let data1 = cpu.load(arg1);
+            let data2 = cpu.load(arg2);
+            check(cpu, &[arg1])?;
+            cpu.store_result(arg1, !(data1 | data2));
+        }
+        Instruction::Not(_, _, _, _, arg1) => {
+            let data1 = cpu.load(arg1);
+            check(cpu, &[arg1])?;
+            cpu.store_result(arg1, !data1);
+        }
+        Instruction::Add(_, _, sign1, sign2, arg1, arg2) => {
+            let data1 = cpu.load(arg1);
+            let data2 = cpu.load(arg2);
+            check(cpu, &[arg1])?;
+            overflow(cpu.store_arithmetic(sign1 || sign2, arg1, data1, data2, |a, b| a + b))?;
+        }
+        Instruction::AddC(_, _, _, _, arg1, arg2) => {
+            let carry = cpu.flags.contains(Flags::CARRY) as i32;
+            let data1 = cpu.load(arg1);
+            let data2 = cpu.load(arg2);
+            check(cpu, &[arg1])?;
+            overflow(cpu.store_arithmetic(false, arg1, data1, data2, |a, b| a + b + carry))?;
+        }
+        Instruction::Sub(_, _, sign1, sign2, arg1, arg2) => {
+            let data1 = cpu.load(arg1);
+            let data2 = cpu.load(arg2);
+            check(cpu, &[arg1])?;
+            overflow(cpu.store_arithmetic(sign1 || sign2, arg1, data1, data2, |a, b| a - b))?;
+        }
+        Instruction::Mul(_, _, sign1, sign2, arg1, arg2) => {
+            let data1 = cpu.load(arg1);
+            let data2 = cpu.load(arg2);
+            check(cpu, &[arg1])?;
+            overflow(cpu.store_arithmetic(sign1 || sign2, arg1, data1, data2, |a, b| a * b))?;
+        }
+        Instruction::Div(halt_on_error, _, sign1, sign2, arg1, arg2) => cpu
+            .division(halt_on_error, sign1, sign2, arg1, arg2, |a, b| a / b)
+            .map_err(Halted::Errored)?,
+        Instruction::Mod(halt_on_error, _, sign1, sign2, arg1, arg2) => cpu
+            .division(halt_on_error, sign1, sign2, arg1, arg2, |a, b| a % b)
+            .map_err(Halted::Errored)?,
+        Instruction::SL(_, _, _, _, arg1, arg2) => {
+            let data1 = cpu.load(arg1);
+            let count = cpu.load(arg2) & 0b111;
+            check(cpu, &[arg1])?;
+            cpu.push(arg1, data1.wrapping_shl(count as u32))
+        }
+        Instruction::SR(_, _, _, _, arg1, arg2) => {
+            let data1 = cpu.load(arg1);
+            let count = cpu.load(arg2) & 0b111;
+            check(cpu, &[arg1])?;
+            cpu.push(arg1, data1.wrapping_shr(count as u32))
+        }
+        Instruction::RL(_, _, _, _, arg1, arg2) => {
+            let data1 = cpu.load(arg1);
+            let count = cpu.load(arg2) & 0b111;
+            check(cpu, &[arg1])?;
+            cpu.push(arg1, u8::rotate_left(data1, count as u32));
+        }
+        Instruction::RR(_, _, _, _, arg1, arg2) => {
+            let data1 = cpu.load(arg1);
+            let count = cpu.load(arg2) & 0b111;
+            check(cpu, &[arg1])?;
+            cpu.push(arg1, u8::rotate_right(data1, count as u32));
+        }
+        Instruction::SRA(_, _, _, _, arg1) => {
+            let data1 = i8::from_be_bytes([cpu.load(arg1)]);
+            check(cpu, &[arg1])?;
+            cpu.push(arg1, (data1 >> 1) as u8);
+        }
+        Instruction::Inc(_, _, sign1, _, arg1) => {
+            let data1 = cpu.load(arg1);
+            check(cpu, &[arg1])?;
+            overflow(cpu.store_arithmetic(sign1, arg1, data1, 1, |a, b| a + b))?;
+        }
+        Instruction::Dec(_, _, sign1, _, arg1) => {
+            let data1 = cpu.load(arg1);
+            check(cpu, &[arg1])?;
+            overflow(cpu.store_arithmetic(sign1, arg1, data1, 1, |a, b| a - b))?;
+        }
+        Instruction::Neg(_, _, _, _, arg1) => {
+            let data1 = cpu.load(arg1);
+            check(cpu, &[arg1])?;
+            overflow(cpu.store_arithmetic(true, arg1, 0, data1, |a, b| a - b))?;
+        }
+        Instruction::Jmp(_, _, _, _, arg1) => {
+            cpu.reg_zero = cpu.load(arg1);
+            advance = 0;
+        }
+        Instruction::JmpRel(_, _, _, _, arg1) => {
+            // The offset is relative to the following instruction
+            advance += extend(cpu.load(arg1), true) as i16;
+        }
+        Instruction::Call(_, _, _, _, arg1) => {
+            let target = cpu.load(arg1);
+            check(cpu, &[])?;
+            if !cpu.stack_push(cpu.reg_zero.wrapping_add(inst.size())) {
+                return Err(Halted::Errored(ErrorKind::StackOverflow));
+            }
+            cpu.reg_zero = target;
+            advance = 0;
+        }
+        Instruction::Ret(_, _, _, _) => match cpu.pop_return() {
+            Some(address) => {
+                cpu.reg_zero = address;
+                advance = 0;
+            }
+            None => return Err(Halted::Errored(ErrorKind::StackUnderflow)),
+        },
+        Instruction::PopCount(_, _, _, _, arg1) => {
+            let data1 = cpu.load(arg1);
+            check(cpu, &[arg1])?;
+            cpu.store_result(arg1, data1.count_ones() as u8);
+        }
+        Instruction::Clz(_, _, _, _, arg1) => {
+            let data1 = cpu.load(arg1);
+            check(cpu, &[arg1])?;
+            cpu.store_result(arg1, data1.leading_zeros() as u8);
+        }
+        Instruction::BankInst(_, _, _, _, arg1) => {
+            let bank = cpu.load(arg1);
+            check(cpu, &[])?;
+            cpu.push(INST_PTR_ADDR, bank);
+        }
+        Instruction::BankData(_, _, _, _, arg1) => {
+            let bank = cpu.load(arg1);
+            check(cpu, &[])?;
+            cpu.push(DATA_PTR_ADDR, bank);
+        }
+        Instruction::PushCtx(_, _, _, _) => {
+            if cpu.sp < 3 || cpu.sp > 64 {
+                return Err(Halted::Errored(ErrorKind::StackOverflow));
+            }
+            let context = [
+                cpu.inst_mem.pointer as u8,
+                cpu.data_mem.pointer as u8,
+                cpu.flags.bits(),
+            ];
+            for data in context {
+                cpu.stack_push(data);
+            }
+        }
+        Instruction::PopCtx(_, _, _, _) => {
+            if cpu.sp > 61 {
+                return Err(Halted::Errored(ErrorKind::StackUnderflow));
+            }
+            let flags = cpu.stack_pop().unwrap_or_default();
+            let data_bank = cpu.stack_pop().unwrap_or_default();
+            let inst_bank = cpu.stack_pop().unwrap_or_default();
+            cpu.flags = Flags::from_bits(flags);
+            cpu.push(DATA_PTR_ADDR, data_bank);
+            cpu.push(INST_PTR_ADDR, inst_bank);
+        }
+        Instruction::Clc(_, _, _, _) => cpu.flags.set(Flags::CARRY, false),
+        Instruction::Sec(_, _, _, _) => cpu.flags.set(Flags::CARRY, true),
+        Instruction::Copy(_, _, _, _, arg1, arg2) => {
+            let data1 = cpu.load(arg1);
+            check(cpu, &[arg2])?;
+            cpu.push(arg2, data1);
+        }
+        Instruction::LoadImm(_, _, _, _, arg1, arg2) => {
+            check(cpu, &[arg1])?;
+            cpu.push(arg1, arg2);
+        }
+        // Swapping a cell with itself does not access it at all
+        Instruction::Swap(_, _, _, _, arg1, arg2) if arg1 == arg2 => (),
+        Instruction::Swap(_, _, _, _, arg1, arg2) => {
+            let data1 = cpu.load(arg1);
+            let data2 = cpu.load(arg2);
+            check(cpu, &[arg1, arg2])?;
+            cpu.push(arg1, data2);
+            cpu.push(arg2, data1);
+        }
+        Instruction::BlockCopy(halt_on_error, _, _, _, arg1, arg2) => cpu
+            .block_copy(halt_on_error, arg1, arg2)
+            .map_err(Halted::Errored)?,
+        Instruction::CompEq(_, skip_count, _, _, arg1, arg2) => {
+            if cpu.load(arg1) != cpu.load(arg2) {
+                advance = cpu.comparison_skip(skip_count, arg2);
+            }
+        }
+        Instruction::CompNe(_, skip_count, _, _, arg1, arg2) => {
+            if cpu.load(arg1) == cpu.load(arg2) {
+                advance = cpu.comparison_skip(skip_count, arg2);
+            }
+        }
+        Instruction::CMovZ(_, _, _, _, arg1, arg2) => {
+            if cpu.zero() {
+                let data1 = cpu.load(arg1);
+                check(cpu, &[arg2])?;
+                cpu.push(arg2, data1);
+            }
+        }
+        Instruction::CMovNz(_, _, _, _, arg1, arg2) => {
+            if !cpu.zero() {
+                let data1 = cpu.load(arg1);
+                check(cpu, &[arg2])?;
+                cpu.push(arg2, data1);
+            }
+        }
+        Instruction::CompGt(_, skip_count, sign1, sign2, arg1, arg2) => {
+            let data1 = extend(cpu.load(arg1), sign1);
+            let data2 = extend(cpu.load(arg2), sign2);
+            if data1 <= data2 {
+                advance = cpu.comparison_skip(skip_count, arg2);
+            }
+        }
+        Instruction::CompLt(_, skip_count, sign1, sign2, arg1, arg2) => {
+            let data1 = extend(cpu.load(arg1), sign1);
+            let data2 = extend(cpu.load(arg2), sign2);
+            if data1 >= data2 {
+                advance = cpu.comparison_skip(skip_count, arg2);
+            }
+        }
+        Instruction::CompGe(_, skip_count, sign1, sign2, arg1, arg2) => {
+            let data1 = extend(cpu.load(arg1), sign1);
+            let data2 = extend(cpu.load(arg2), sign2);
+            if data1 < data2 {
+                advance = cpu.comparison_skip(skip_count, arg2);
+            }
+        }
+        Instruction::CompLe(_, skip_count, sign1, sign2, arg1, arg2) => {
+            let data1 = extend(cpu.load(arg1), sign1);
+            let data2 = extend(cpu.load(arg2), sign2);
+            if data1 > data2 {
+                advance = cpu.comparison_skip(skip_count, arg2);
+            }
+        }
+    };
+    Ok(advance)
+}
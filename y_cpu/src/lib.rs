@@ -1,318 +1,1749 @@
-use std::ops::{Index, IndexMut, Range};
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod analysis;
+pub mod assembler;
+pub mod devices;
+mod dispatch;
+pub mod loader;
+#[cfg(feature = "serde")]
+mod serde_impls;
+
+use alloc::{boxed::Box, collections::BTreeSet, format, string::String, vec, vec::Vec};
+use core::{
+    fmt,
+    ops::{Index, IndexMut, Range, RangeInclusive},
+};
 
 pub struct CPU {
     pub reg_zero: u8,
+    pub flags: Flags,
+    /// Offset of the top of the stack in data bank 0, the stack grows downwards from the end
+    /// of the bank
+    pub sp: u8,
     pub inst_mem: Banker<[u8; 127]>,
+    /// Instruction memory replacing the banked one in flat mode, see `new_flat`
+    flat_mem: Option<Vec<u8>>,
     pub data_mem: Banker<[u8; 64]>,
     pub devices: Vec<Box<dyn Device>>,
+    /// Index in `devices` of the device mapped to each address from `DEVICE_BASE` on
+    device_map: [Option<usize>; DEVICE_SLOTS],
+    /// Instructions executed since each device last ticked
+    device_clocks: Vec<u32>,
+    /// Whether devices requesting an interrupt are serviced
+    pub interrupts_enabled: bool,
+    /// Stack pointer right after an interrupt pushed its return address, while its handler
+    /// runs. Other interrupts are masked until then
+    handler_sp: Option<u8>,
+    /// What Add, Sub, Mul, Inc, Dec and Neg do with results that do not fit
+    pub arith_mode: ArithMode,
+    /// Whether instructions may write to instruction memory (addresses 1 to 127)
+    pub allow_self_modify: bool,
+    /// Like turning `allow_self_modify` off for good, see `CpuBuilder::read_only_code`
+    read_only_code: bool,
+    pub debug_log: Vec<DebugRecord>,
+    pub watch_log: Vec<WatchRecord>,
+    /// Whether every memory access done by instructions is recorded in `access_log`
+    pub log_accesses: bool,
+    pub access_log: Vec<AccessEvent>,
+    /// Address of the instruction being executed
+    exec_pc: u8,
+    /// Address of the instruction being executed, if it has its debug bit set
+    debug_pc: Option<u8>,
+    /// The first memory access of the instruction being executed that failed
+    access_fault: Option<CpuError>,
+    trace_hook: Option<TraceHook>,
+    breakpoints: BTreeSet<u8>,
+    watchpoints: BTreeSet<u8>,
+    /// Number of executed instructions for each opcode
+    opcode_counts: [u64; 16],
+    cycles: u64,
+    cost_model: CostModel,
+    /// Decoded instruction at each program counter of the current bank, when enabled
+    decode_cache: Option<Vec<Option<Instruction>>>,
 }
 
-pub struct Banker<T> {
-    pub content: [T; 256],
-    pub pointer: u8,
+/// Copies the whole machine, devices included. The trace hook is not carried over
+impl Clone for CPU {
+    fn clone(&self) -> CPU {
+        CPU {
+            reg_zero: self.reg_zero,
+            flags: self.flags,
+            sp: self.sp,
+            inst_mem: self.inst_mem.clone(),
+            flat_mem: self.flat_mem.clone(),
+            data_mem: self.data_mem.clone(),
+            devices: self
+                .devices
+                .iter()
+                .map(|device| device.clone_box())
+                .collect(),
+            device_map: self.device_map,
+            device_clocks: self.device_clocks.clone(),
+            interrupts_enabled: self.interrupts_enabled,
+            handler_sp: self.handler_sp,
+            arith_mode: self.arith_mode,
+            allow_self_modify: self.allow_self_modify,
+            read_only_code: self.read_only_code,
+            debug_log: self.debug_log.clone(),
+            watch_log: self.watch_log.clone(),
+            log_accesses: self.log_accesses,
+            access_log: self.access_log.clone(),
+            exec_pc: self.exec_pc,
+            debug_pc: self.debug_pc,
+            access_fault: self.access_fault,
+            trace_hook: None,
+            breakpoints: self.breakpoints.clone(),
+            watchpoints: self.watchpoints.clone(),
+            opcode_counts: self.opcode_counts,
+            cycles: self.cycles,
+            cost_model: self.cost_model,
+            decode_cache: self.decode_cache.clone(),
+        }
+    }
 }
 
-impl<T: std::marker::Copy + Index<usize>> Banker<T> {
-    pub fn new(content: T) -> Banker<T> {
+/// Shows the registers, the next instruction and the start of the current data bank
+impl fmt::Debug for CPU {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let next = self.fetch();
+        let next: &dyn fmt::Display = match &next {
+            Some(inst) => inst,
+            None => &"<end of instruction memory>",
+        };
+        f.debug_struct("CPU")
+            .field("reg_zero", &self.reg_zero)
+            .field("inst_bank", &self.inst_mem.pointer)
+            .field("data_bank", &self.data_mem.pointer)
+            .field("sp", &self.sp)
+            .field("flags", &self.flags)
+            .field("next", &format_args!("{}", next))
+            .field(
+                "data",
+                &self
+                    .data_mem
+                    .try_current_bank()
+                    .map_or(&[][..], |bank| &bank[..16]),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+/// Number of cycles an instruction takes
+pub type CostModel = fn(&Instruction) -> u64;
+
+/// Called with the program counter and the instruction about to be executed
+pub type TraceHook = Box<dyn FnMut(u8, &Instruction)>;
+
+/// A write done by an instruction with the debug bit set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DebugRecord {
+    pub pc: u8,
+    pub addr: u8,
+    pub data: u8,
+}
+
+/// A write to a watched address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchRecord {
+    pub pc: u8,
+    pub addr: u8,
+    pub old: u8,
+    pub new: u8,
+}
+
+/// A memory access done by an instruction, with the value it read or wrote
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessEvent {
+    Load { pc: u8, addr: u8, value: u8 },
+    Store { pc: u8, addr: u8, value: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ArithMode {
+    /// Keep the low 8 bits of the result
+    #[default]
+    Wrapping,
+    /// Stop with an error, leaving the destination unchanged
+    Trapping,
+    /// Clamp the result to the range of the destination
+    Saturating,
+}
+
+/// What the next tick would do, as returned by `CPU::preview`
+#[derive(Debug, PartialEq, Eq)]
+pub struct StepEffect {
+    pub instruction: Instruction,
+    /// Addresses read, along with the value they held
+    pub reads: Vec<(u8, u8)>,
+    /// Addresses written, along with the value stored
+    pub writes: Vec<(u8, u8)>,
+    pub flags: Flags,
+    pub next_pc: u16,
+    pub halted: Halted,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    DeviceCountMismatch { expected: usize, found: usize },
+}
+
+/// Address selecting the current instruction bank
+pub const INST_PTR_ADDR: u8 = 192;
+/// Address selecting the current data bank
+pub const DATA_PTR_ADDR: u8 = 193;
+/// First address mapped to devices, every address from there on can hold one except
+/// `FLAGS_ADDR`
+pub const DEVICE_BASE: u8 = 194;
+/// Address of the flags register, laid out like `Flags`
+pub const FLAGS_ADDR: u8 = 195;
+const DEVICE_SLOTS: usize = 256 - DEVICE_BASE as usize;
+
+const STATE_MAGIC: &[u8; 4] = b"YCPU";
+const STATE_VERSION: u8 = 1;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The bytes do not encode an instruction the way `to_3bytes` would, because they use a
+    /// reserved opcode or selector, or set bits the instruction does not use
+    Reserved([u8; 3]),
+    /// An argument is an address with no device plugged in
+    BadAddress(u8),
+}
+
+/// Why a memory access failed, returned by `read`, `write`, `try_load` and `try_push`.
+/// Instructions hitting one of these count as faulting instead
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+    UnmappedDevice(u8),
+    /// A write to instruction memory while `allow_self_modify` is off
+    ReadOnly(u8),
+    /// An address past the end of its memory bank, or in a bank that does not exist
+    OutOfBounds(u8),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CpuInitError {
+    AddressOutOfRange(u8),
+    DuplicateAddress(u8),
+    ProgramTooLarge(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Flags(u8);
+
+impl Flags {
+    pub const ZERO: Flags = Flags(0b0000_0001);
+    pub const CARRY: Flags = Flags(0b0000_0010);
+    pub const OVERFLOW: Flags = Flags(0b0000_0100);
+    pub const NEGATIVE: Flags = Flags(0b0000_1000);
+
+    pub fn empty() -> Flags {
+        Flags(0)
+    }
+
+    pub fn from_bits(bits: u8) -> Flags {
+        Flags(bits & 0b0000_1111)
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(&self, other: Flags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn set(&mut self, other: Flags, value: bool) {
+        if value {
+            self.0 |= other.0;
+        } else {
+            self.0 &= !other.0;
+        }
+    }
+}
+
+/// A bank past the last one of a `Banker`, returned by `switch_to`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoSuchBank(pub usize);
+
+#[derive(Clone)]
+pub struct Banker<T, const N: usize = 256> {
+    pub content: [T; N],
+    pub pointer: usize,
+}
+
+impl<T: core::marker::Copy + Index<usize>, const N: usize> Banker<T, N> {
+    pub fn new(content: T) -> Banker<T, N> {
+        let () = Self::POINTER_FITS_A_BYTE;
+        Banker {
+            content: [content; N],
+            pointer: 0,
+        }
+    }
+}
+
+impl<T, const N: usize> Banker<T, N> {
+    /// The bank selectors are a single byte wide, so every bank must be reachable from one
+    const POINTER_FITS_A_BYTE: () = assert!(N <= 256, "a Banker holds at most 256 banks");
+
+    /// Puts `content` in the first bank only, the other ones start out with their default
+    pub fn with_first_bank(content: T) -> Banker<T, N>
+    where
+        T: Default,
+    {
+        Self::with_first_bank_and_fill(content, T::default)
+    }
+
+    /// Puts `content` in the first bank only and builds each of the other ones with `fill`,
+    /// for banks like large arrays which have no default
+    pub fn with_first_bank_and_fill(content: T, mut fill: impl FnMut() -> T) -> Banker<T, N> {
+        let () = Self::POINTER_FITS_A_BYTE;
+        let mut first = Some(content);
         Banker {
-            content: [content; 256],
+            content: core::array::from_fn(|_| first.take().unwrap_or_else(&mut fill)),
             pointer: 0,
         }
     }
+
+    pub fn current_bank(&self) -> &T {
+        &self.content[self.pointer]
+    }
+
+    pub fn current_bank_mut(&mut self) -> &mut T {
+        &mut self.content[self.pointer]
+    }
+
+    /// The current bank, `None` if the pointer was set past the last one
+    pub fn try_current_bank(&self) -> Option<&T> {
+        self.content.get(self.pointer)
+    }
+
+    /// Selects `bank`, leaving the pointer as it was if there is no such bank
+    pub fn switch_to(&mut self, bank: usize) -> Result<(), NoSuchBank> {
+        if bank >= N {
+            return Err(NoSuchBank(bank));
+        }
+        self.pointer = bank;
+        Ok(())
+    }
+
+    /// Reads from the current bank, `None` if the bank or the index is out of bounds
+    pub fn try_get<U: core::marker::Copy>(&self, index: usize) -> Option<U>
+    where
+        T: AsRef<[U]>,
+    {
+        self.content.get(self.pointer)?.as_ref().get(index).copied()
+    }
+
+    /// Writes to the current bank, `None` if the bank or the index is out of bounds
+    pub fn try_set<U>(&mut self, index: usize, value: U) -> Option<()>
+    where
+        T: AsMut<[U]>,
+    {
+        *self
+            .content
+            .get_mut(self.pointer)?
+            .as_mut()
+            .get_mut(index)? = value;
+        Some(())
+    }
 }
 
-impl<T: Index<usize>> Index<usize> for Banker<T> {
+impl<T: Index<usize>, const N: usize> Index<usize> for Banker<T, N> {
     type Output = <T as Index<usize>>::Output;
     fn index(&self, index: usize) -> &Self::Output {
-        &self.content[self.pointer as usize][index as usize]
+        &self.content[self.pointer][index]
     }
 }
 
-impl<T: IndexMut<usize>> IndexMut<usize> for Banker<T> {
+impl<T: IndexMut<usize>, const N: usize> IndexMut<usize> for Banker<T, N> {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        &mut self.content[self.pointer as usize][index as usize]
+        &mut self.content[self.pointer][index]
     }
 }
 
-impl<T: Index<Range<usize>>> Index<Range<usize>> for Banker<T> {
+impl<T: Index<Range<usize>>, const N: usize> Index<Range<usize>> for Banker<T, N> {
     type Output = <T as Index<Range<usize>>>::Output;
-    fn index(&self, index: std::ops::Range<usize>) -> &Self::Output {
-        &self.content[self.pointer as usize][index]
+    fn index(&self, index: Range<usize>) -> &Self::Output {
+        &self.content[self.pointer][index]
     }
 }
 
-impl<T: IndexMut<Range<usize>>> IndexMut<Range<usize>> for Banker<T> {
-    fn index_mut(&mut self, index: std::ops::Range<usize>) -> &mut Self::Output {
-        &mut self.content[self.pointer as usize][index]
+impl<T: IndexMut<Range<usize>>, const N: usize> IndexMut<Range<usize>> for Banker<T, N> {
+    fn index_mut(&mut self, index: Range<usize>) -> &mut Self::Output {
+        &mut self.content[self.pointer][index]
     }
 }
 
-impl CPU {
-    pub fn new(inst_mem: [u8; 127], devices: Vec<Box<dyn Device>>) -> CPU {
-        let mut mapped_devices = Vec::with_capacity(62);
-        for device in devices.into_iter() {
-            let address = device.address() as usize - 194;
-            mapped_devices[address] = device;
+#[derive(Default)]
+pub struct CpuBuilder {
+    instructions: Vec<u8>,
+    initial_data: Option<[u8; 64]>,
+    devices: Vec<Box<dyn Device>>,
+    read_only_code: bool,
+}
+
+impl CpuBuilder {
+    pub fn new() -> CpuBuilder {
+        CpuBuilder::default()
+    }
+
+    /// The program, padded with zeroes up to the size of instruction memory
+    pub fn instructions(mut self, instructions: &[u8]) -> CpuBuilder {
+        self.instructions = instructions.to_vec();
+        self
+    }
+
+    /// Content of the first data bank
+    pub fn initial_data(mut self, data: &[u8; 64]) -> CpuBuilder {
+        self.initial_data = Some(*data);
+        self
+    }
+
+    pub fn device(mut self, device: Box<dyn Device>) -> CpuBuilder {
+        self.devices.push(device);
+        self
+    }
+
+    /// Rejects every write to instruction memory once the CPU is built, whatever
+    /// `allow_self_modify` is set to. Such a write always stops execution with an error
+    pub fn read_only_code(mut self, read_only: bool) -> CpuBuilder {
+        self.read_only_code = read_only;
+        self
+    }
+
+    pub fn build(self) -> Result<CPU, CpuInitError> {
+        let mut inst_mem = [0; 127];
+        if self.instructions.len() > inst_mem.len() {
+            return Err(CpuInitError::ProgramTooLarge(self.instructions.len()));
         }
-        CPU {
+        inst_mem[..self.instructions.len()].copy_from_slice(&self.instructions);
+        let mut cpu = CPU::new(inst_mem, self.devices)?;
+        if let Some(data) = self.initial_data {
+            *cpu.data_mem.current_bank_mut() = data;
+        }
+        cpu.read_only_code = self.read_only_code;
+        Ok(cpu)
+    }
+}
+
+impl CPU {
+    /// Loads `inst_mem` in instruction bank 0, every other bank starts out empty
+    pub fn new(inst_mem: [u8; 127], devices: Vec<Box<dyn Device>>) -> Result<CPU, CpuInitError> {
+        let mut cpu = CPU {
             reg_zero: 0,
-            inst_mem: Banker::new(inst_mem),
-            data_mem: Banker::new([0; 64]),
-            devices: mapped_devices,
+            flags: Flags::empty(),
+            sp: 64,
+            inst_mem: Banker::with_first_bank_and_fill(inst_mem, || [0; 127]),
+            flat_mem: None,
+            data_mem: Banker::with_first_bank_and_fill([0; 64], || [0; 64]),
+            devices: Vec::new(),
+            device_map: [None; DEVICE_SLOTS],
+            device_clocks: Vec::new(),
+            interrupts_enabled: true,
+            handler_sp: None,
+            arith_mode: ArithMode::Wrapping,
+            allow_self_modify: true,
+            read_only_code: false,
+            debug_log: Vec::new(),
+            watch_log: Vec::new(),
+            log_accesses: false,
+            access_log: Vec::new(),
+            exec_pc: 0,
+            debug_pc: None,
+            access_fault: None,
+            trace_hook: None,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            opcode_counts: [0; 16],
+            cycles: 0,
+            cost_model: |_| 1,
+            decode_cache: None,
+        };
+        for device in devices {
+            cpu.add_device(device)?;
+        }
+        Ok(cpu)
+    }
+
+    /// Plugs a device in at the addresses it asks for, which must all be free
+    pub fn add_device(&mut self, device: Box<dyn Device>) -> Result<(), CpuInitError> {
+        for address in device.address_range() {
+            if address < DEVICE_BASE || address == FLAGS_ADDR {
+                return Err(CpuInitError::AddressOutOfRange(address));
+            }
+            if self.device_index(address).is_some() {
+                return Err(CpuInitError::DuplicateAddress(address));
+            }
+        }
+        for address in device.address_range() {
+            self.device_map[(address - DEVICE_BASE) as usize] = Some(self.devices.len());
+        }
+        self.devices.push(device);
+        self.device_clocks.push(0);
+        Ok(())
+    }
+
+    /// Unplugs the device mapped to `addr`, freeing every address it used
+    pub fn remove_device(&mut self, addr: u8) -> Option<Box<dyn Device>> {
+        let index = self.device_index(addr)?;
+        for slot in self.device_map.iter_mut() {
+            *slot = match *slot {
+                Some(i) if i == index => None,
+                Some(i) if i > index => Some(i - 1),
+                other => other,
+            };
         }
+        self.device_clocks.remove(index);
+        Some(self.devices.remove(index))
+    }
+
+    /// Creates a CPU in flat mode: instructions are fetched from a single 65536 byte memory
+    /// holding `program` at `origin`, where execution starts. The program counter is 16 bits
+    /// wide, its low byte is at address 0 and its high byte at `INST_PTR_ADDR`, so jumps stay
+    /// in the current 256 byte page, where addresses 1 to 127 point to. Save states do not
+    /// include flat memory
+    pub fn new_flat(
+        program: &[u8],
+        origin: u16,
+        devices: Vec<Box<dyn Device>>,
+    ) -> Result<CPU, CpuInitError> {
+        let mut flat_mem = vec![0; 0x10000];
+        flat_mem
+            .get_mut(origin as usize..origin as usize + program.len())
+            .ok_or(CpuInitError::ProgramTooLarge(program.len()))?
+            .copy_from_slice(program);
+        let mut cpu = CPU::new([0; 127], devices)?;
+        cpu.flat_mem = Some(flat_mem);
+        cpu.set_pc(origin);
+        Ok(cpu)
+    }
+
+    /// The program counter, only the low byte of which is used outside of flat mode
+    pub fn pc(&self) -> u16 {
+        match self.flat_mem {
+            Some(_) => u16::from_be_bytes([self.inst_mem.pointer as u8, self.reg_zero]),
+            None => self.reg_zero as u16,
+        }
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        let [high, low] = pc.to_be_bytes();
+        self.reg_zero = low;
+        if self.flat_mem.is_some() {
+            self.inst_mem.pointer = high as usize;
+        }
+    }
+
+    /// Puts the CPU back in its initial state so the program can be run again. Instruction
+    /// memory is left as is, the data memory is cleared and every device is reset
+    pub fn reset(&mut self) {
+        self.reg_zero = 0;
+        self.flags = Flags::empty();
+        self.sp = 64;
+        self.handler_sp = None;
+        self.inst_mem.pointer = 0;
+        self.invalidate_decode_cache();
+        self.data_mem = Banker::with_first_bank_and_fill([0; 64], || [0; 64]);
+        self.cycles = 0;
+        self.device_clocks.fill(0);
+        for device in self.devices.iter_mut() {
+            device.reset();
+        }
+    }
+
+    pub fn clear_access_log(&mut self) {
+        self.access_log.clear();
+    }
+
+    /// Replaces the whole content of a data bank, to preload tables or constants
+    pub fn set_data_bank(&mut self, bank: u8, data: [u8; 64]) {
+        self.data_mem.content[bank as usize] = data;
     }
 
     pub fn tick(&mut self) -> Halted {
-        let inst = self.fetch();
-        self.process(inst)
+        self.step().1
     }
 
-    pub fn fetch(&self) -> Instruction {
-        Instruction::from_3bytes([
-            self.inst_mem[self.reg_zero as usize],
-            self.inst_mem[(self.reg_zero + 1) as usize],
-            self.inst_mem[(self.reg_zero + 2) as usize],
-        ])
+    /// Executes one instruction and returns it along with the resulting state. Running past
+    /// the end of instruction memory is reported as a `Halt` instruction
+    pub fn step(&mut self) -> (Instruction, Halted) {
+        match self.fetch_cached() {
+            Some(inst) => {
+                if let Some(hook) = &mut self.trace_hook {
+                    hook(self.reg_zero, &inst);
+                }
+                self.cycles += (self.cost_model)(&inst);
+                #[cfg(feature = "logging")]
+                let pc = self.pc();
+                let mut halted = self.process(inst);
+                #[cfg(feature = "logging")]
+                log::trace!("{:#04x}: {} -> {:?}", pc, inst, halted);
+                for (device, clock) in self.devices.iter_mut().zip(&mut self.device_clocks) {
+                    *clock += 1;
+                    if *clock >= device.clock_divider() {
+                        *clock = 0;
+                        device.tick();
+                    }
+                }
+                if halted == Halted::Running && self.interrupts_enabled {
+                    halted = self.interrupt();
+                }
+                (inst, halted)
+            }
+            None => (
+                Instruction::Halt(false, false, false, false),
+                Halted::Halted,
+            ),
+        }
     }
 
-    fn process(&mut self, inst: Instruction) -> Halted {
-        match inst {
-            Instruction::NoOp(_, _, _, _) => (),
-            Instruction::And(_, _, _, _, arg1, arg2) => {
-                let data1 = self.load(arg1);
-                let data2 = self.load(arg2);
-                self.push(arg1, data1 & data2);
+    /// Reports what the next tick would do, by running it on a copy of the machine. Devices
+    /// are copied as well, so the real ones do not see the accesses
+    pub fn preview(&self) -> StepEffect {
+        let mut copy = self.clone();
+        copy.log_accesses = true;
+        copy.access_log.clear();
+        let (instruction, halted) = copy.step();
+        let (mut reads, mut writes) = (Vec::new(), Vec::new());
+        for event in copy.access_log.iter() {
+            match *event {
+                AccessEvent::Load { addr, value, .. } => reads.push((addr, value)),
+                AccessEvent::Store { addr, value, .. } => writes.push((addr, value)),
             }
-            Instruction::Or(_, _, _, _, arg1, arg2) => {
-                let data1 = self.load(arg1);
-                let data2 = self.load(arg2);
-                self.push(arg1, data1 | data2);
+        }
+        StepEffect {
+            instruction,
+            reads,
+            writes,
+            flags: copy.flags,
+            next_pc: copy.pc(),
+            halted,
+        }
+    }
+
+    /// Steps until the program stops, yielding the program counter, the instruction and the
+    /// resulting state each time. The step that halts or errors is the last one yielded
+    pub fn steps(&mut self) -> impl Iterator<Item = (u8, Instruction, Halted)> + '_ {
+        let mut stopped = false;
+        core::iter::from_fn(move || {
+            if stopped {
+                return None;
             }
-            Instruction::Not(_, _, _, _, arg1) => {
-                let data1 = self.load(arg1);
-                self.push(arg1, !data1);
+            let pc = self.reg_zero;
+            let (inst, halted) = self.step();
+            stopped = halted != Halted::Running;
+            Some((pc, inst, halted))
+        })
+    }
+
+    /// Installs a hook called with the program counter and the instruction before each tick
+    /// executes it. The `logging` feature logs every executed instruction as well, along
+    /// with its result
+    pub fn set_trace_hook(&mut self, hook: TraceHook) {
+        self.trace_hook = Some(hook);
+    }
+
+    /// Ticks until the program halts or errors, or until `max_steps` instructions ran.
+    /// Returns the final state along with the number of ticks
+    pub fn run(&mut self, max_steps: usize) -> (Halted, usize) {
+        for step in 1..=max_steps {
+            match self.tick() {
+                Halted::Running => (),
+                halted => return (halted, step),
             }
-            Instruction::Add(_, _, sign1, sign2, arg1, arg2) => {
-                match (sign1, sign2) {
-                    (true, true) => {
-                        let data1 = i8::from_be_bytes([self.load(arg1)]);
-                        let data2 = i8::from_be_bytes([self.load(arg2)]);
-                        self.push(arg1, (data1 + data2) as u8);
-                    }
-                    (true, false) => {
-                        let data1 = i8::from_be_bytes([self.load(arg1)]);
-                        let data2 = self.load(arg2);
-                        self.push(arg1, (data1 as i16 + data2 as i16) as u8);
-                    }
-                    (false, true) => {
-                        let data1 = self.load(arg1);
-                        let data2 = i8::from_be_bytes([self.load(arg2)]);
-                        self.push(arg1, (data1 as i16 + data2 as i16) as u8);
-                    }
-                    (false, false) => {
-                        let data1 = self.load(arg1);
-                        let data2 = self.load(arg2);
-                        self.push(arg1, data1 + data2);
-                    }
-                };
+        }
+        (Halted::Running, max_steps)
+    }
+
+    /// Ticks until the program halts or errors, or until `max_cycles` cycles were spent as
+    /// counted by the cost model. An instruction that would go over the budget is left for
+    /// the next call, and one costing no cycle is counted as one so that the loop ends.
+    /// Returns the final state along with the number of cycles spent from the budget
+    pub fn run_cycles(&mut self, max_cycles: u64) -> (Halted, u64) {
+        let mut spent = 0;
+        loop {
+            let cost = self
+                .fetch()
+                .map_or(0, |inst| (self.cost_model)(&inst).max(1));
+            if spent + cost > max_cycles {
+                return (Halted::Running, spent);
             }
-            Instruction::Sub(_, _, sign1, sign2, arg1, arg2) => match (sign1, sign2) {
-                (true, true) => {
-                    let data1 = i8::from_be_bytes([self.load(arg1)]);
-                    let data2 = i8::from_be_bytes([self.load(arg2)]);
-                    self.push(arg1, (data1 - data2) as u8);
-                }
-                (true, false) => {
-                    let data1 = i8::from_be_bytes([self.load(arg1)]);
-                    let data2 = self.load(arg2);
-                    self.push(arg1, (data1 as i16 - data2 as i16) as u8);
-                }
-                (false, true) => {
-                    let data1 = self.load(arg1);
-                    let data2 = i8::from_be_bytes([self.load(arg2)]);
-                    self.push(arg1, (data1 as i16 - data2 as i16) as u8);
-                }
-                (false, false) => {
-                    let data1 = self.load(arg1);
-                    let data2 = self.load(arg2);
-                    self.push(arg1, data1 - data2);
-                }
-            },
-            Instruction::Mul(_, _, sign1, sign2, arg1, arg2) => match (sign1, sign2) {
-                (true, true) => {
-                    let data1 = i8::from_be_bytes([self.load(arg1)]);
-                    let data2 = i8::from_be_bytes([self.load(arg2)]);
-                    self.push(arg1, (data1 * data2) as u8);
-                }
-                (true, false) => {
-                    let data1 = i8::from_be_bytes([self.load(arg1)]);
-                    let data2 = self.load(arg2);
-                    self.push(arg1, (data1 as i16 * data2 as i16) as u8);
-                }
-                (false, true) => {
-                    let data1 = self.load(arg1);
-                    let data2 = i8::from_be_bytes([self.load(arg2)]);
-                    self.push(arg1, (data1 as i16 * data2 as i16) as u8);
-                }
-                (false, false) => {
-                    let data1 = self.load(arg1);
-                    let data2 = self.load(arg2);
-                    self.push(arg1, data1 * data2);
-                }
-            },
-            Instruction::Div(_, _, sign1, sign2, arg1, arg2) => match (sign1, sign2) {
-                (true, true) => {
-                    let data1 = i8::from_be_bytes([self.load(arg1)]);
-                    let data2 = i8::from_be_bytes([self.load(arg2)]);
-                    self.push(arg1, (data1 / data2) as u8);
-                }
-                (true, false) => {
-                    let data1 = i8::from_be_bytes([self.load(arg1)]);
-                    let data2 = self.load(arg2);
-                    self.push(arg1, (data1 as i16 / data2 as i16) as u8);
-                }
-                (false, true) => {
-                    let data1 = self.load(arg1);
-                    let data2 = i8::from_be_bytes([self.load(arg2)]);
-                    self.push(arg1, (data1 as i16 / data2 as i16) as u8);
-                }
-                (false, false) => {
-                    let data1 = self.load(arg1);
-                    let data2 = self.load(arg2);
-                    self.push(arg1, data1 / data2);
-                }
-            },
-            Instruction::SL(_, _, _, _, arg1) => {
-                let data1 = self.load(arg1);
-                self.push(arg1, data1 << 1)
+            spent += cost;
+            match self.tick() {
+                Halted::Running => (),
+                halted => return (halted, spent),
             }
-            Instruction::SR(_, _, _, _, arg1) => {
-                let data1 = self.load(arg1);
-                self.push(arg1, data1 >> 1)
+        }
+    }
+
+    /// Executes up to `n` instructions, stopping early if the program halts or errors.
+    /// Returns the final state along with the number of instructions executed, which does
+    /// not count running past the end of instruction memory
+    pub fn tick_n(&mut self, n: usize) -> (Halted, usize) {
+        for executed in 0..n {
+            if self.past_end() {
+                return (Halted::Halted, executed);
             }
-            Instruction::RL(_, _, _, _, arg1) => {
-                let data1 = self.load(arg1);
-                self.push(arg1, u8::rotate_left(data1, 1));
+            match self.tick() {
+                Halted::Running => (),
+                halted => return (halted, executed + 1),
             }
-            Instruction::RR(_, _, _, _, arg1) => {
-                let data1 = self.load(arg1);
-                self.push(arg1, u8::rotate_right(data1, 1));
+        }
+        (Halted::Running, n)
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u8) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u8) {
+        self.breakpoints.remove(&addr);
+    }
+
+    /// Replaces the default cost model, where every instruction takes a single cycle
+    pub fn set_cost_model(&mut self, cost_model: CostModel) {
+        self.cost_model = cost_model;
+    }
+
+    /// Elapsed cycles since the CPU was created or reset
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// How many instructions were executed for each opcode, indexed by the opcode's 4 bits
+    pub fn opcode_counts(&self) -> [u64; 16] {
+        self.opcode_counts
+    }
+
+    pub fn reset_counts(&mut self) {
+        self.opcode_counts = [0; 16];
+    }
+
+    /// Records every write to `addr` in the watch log. Device addresses cannot be watched
+    pub fn watch(&mut self, addr: u8) {
+        self.watchpoints.insert(addr);
+    }
+
+    pub fn unwatch(&mut self, addr: u8) {
+        self.watchpoints.remove(&addr);
+    }
+
+    /// Ticks until the program counter reaches a breakpoint, the program halts or errors,
+    /// or `max_steps` instructions ran. The instruction at a breakpoint is not executed,
+    /// except for the very first one so that a stopped program can be resumed
+    pub fn run_to_breakpoint(&mut self, max_steps: usize) -> StopReason {
+        for step in 0..max_steps {
+            if step > 0 && self.breakpoints.contains(&self.reg_zero) {
+                return StopReason::Breakpoint(self.reg_zero);
             }
-            Instruction::Copy(_, _, _, _, arg1, arg2) => {
-                let data1 = self.load(arg1);
-                self.push(arg2, data1);
+            match self.tick() {
+                Halted::Running => (),
+                Halted::Halted => return StopReason::Halted,
+                Halted::Errored(kind) => return StopReason::Errored(kind),
             }
-            Instruction::CompEq(_, _, _, _, arg1, arg2) => {
-                let data1 = self.load(arg1);
-                let data2 = self.load(arg2);
-                if data1 != data2 {
-                    self.reg_zero += 3;
-                }
+        }
+        if self.breakpoints.contains(&self.reg_zero) {
+            return StopReason::Breakpoint(self.reg_zero);
+        }
+        StopReason::StepLimit
+    }
+
+    /// Decodes the instruction at the program counter in the current instruction bank, or
+    /// returns `None` when it does not fit in instruction memory anymore or when the bank does
+    /// not exist. Switching banks does not touch the program counter, execution carries on at
+    /// the same address in the new bank
+    pub fn fetch(&self) -> Option<Instruction> {
+        if self.past_end() {
+            return None;
+        }
+        if let Some(flat_mem) = &self.flat_mem {
+            let pc = self.pc() as usize;
+            return Some(Instruction::from_3bytes([
+                flat_mem[pc],
+                flat_mem[pc + 1],
+                flat_mem[pc + 2],
+            ]));
+        }
+        let pc = self.reg_zero as usize;
+        Some(Instruction::from_3bytes([
+            self.inst_mem.try_get(pc)?,
+            self.inst_mem.try_get(pc + 1)?,
+            self.inst_mem.try_get(pc + 2)?,
+        ]))
+    }
+
+    /// Whether the program counter points to an instruction that does not fit in instruction
+    /// memory
+    fn past_end(&self) -> bool {
+        match &self.flat_mem {
+            Some(flat_mem) => self.pc() as usize + Instruction::MAX_SIZE as usize > flat_mem.len(),
+            None => self.reg_zero as usize + Instruction::MAX_SIZE as usize > 127,
+        }
+    }
+
+    /// Decodes an instruction, rejecting encodings which would only decode by falling back
+    /// to another instruction, and arguments which address an empty device slot
+    pub fn decode_checked(&self, bytes: [u8; 3]) -> Result<Instruction, DecodeError> {
+        let inst = Instruction::from_3bytes(bytes);
+        if inst.to_3bytes() != bytes {
+            return Err(DecodeError::Reserved(bytes));
+        }
+        for addr in inst.addresses() {
+            if addr >= DEVICE_BASE && addr != FLAGS_ADDR && self.device_index(addr).is_none() {
+                return Err(DecodeError::BadAddress(addr));
             }
-            Instruction::CompGt(_, _, _, _, arg1, arg2) => {
-                let data1 = self.load(arg1);
-                let data2 = self.load(arg2);
-                if data1 <= data2 {
-                    self.reg_zero += 3;
-                }
+        }
+        Ok(inst)
+    }
+
+    /// Keeps decoded instructions around instead of decoding them on every tick. Writes done
+    /// by instructions keep the cache up to date, but modifying `inst_mem` directly requires
+    /// calling `invalidate_decode_cache`. Flat mode does not use the cache
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = enabled.then(|| vec![None; 127]);
+    }
+
+    pub fn invalidate_decode_cache(&mut self) {
+        if let Some(cache) = &mut self.decode_cache {
+            cache.fill(None);
+        }
+    }
+
+    fn fetch_cached(&mut self) -> Option<Instruction> {
+        if self.flat_mem.is_some() {
+            return self.fetch();
+        }
+        let pc = self.reg_zero as usize;
+        if let Some(Some(inst)) = self.decode_cache.as_ref().and_then(|cache| cache.get(pc)) {
+            return Some(*inst);
+        }
+        let inst = self.fetch()?;
+        if let Some(cache) = &mut self.decode_cache {
+            cache[pc] = Some(inst);
+        }
+        Some(inst)
+    }
+
+    fn process(&mut self, inst: Instruction) -> Halted {
+        self.process_with(inst, |cpu, bytes| cpu.execute(bytes))
+    }
+
+    /// Runs `inst` through `execute`, which the tests also call with the match dispatch the
+    /// opcode handlers replaced
+    fn process_with(
+        &mut self,
+        inst: Instruction,
+        execute: impl FnOnce(&mut CPU, [u8; 3]) -> Halted,
+    ) -> Halted {
+        let bytes = inst.to_3bytes();
+        let first_byte = bytes[0];
+        self.opcode_counts[(first_byte & 0b0000_1111) as usize] += 1;
+        let store_debug_info = first_byte & 0b0100_0000 == 0b0100_0000;
+        self.exec_pc = self.reg_zero;
+        self.debug_pc = store_debug_info.then_some(self.reg_zero);
+        self.access_fault = None;
+        let mut halted = execute(self, bytes);
+        self.debug_pc = None;
+        match self.access_fault {
+            Some(fault)
+                if (inst.flags().halt_on_error || self.protects(fault))
+                    && halted == Halted::Running =>
+            {
+                // Stop on the faulting instruction, like the other errors
+                self.reg_zero = self.exec_pc;
+                halted = Halted::Errored(fault.into());
             }
-            Instruction::CompLt(_, _, _, _, arg1, arg2) => {
-                let data1 = self.load(arg1);
-                let data2 = self.load(arg2);
-                if data1 >= data2 {
-                    self.reg_zero += 3;
-                }
+            _ => (),
+        }
+        halted
+    }
+
+    /// Code protected for good cannot be written to, whether the instruction asks to halt or
+    /// not
+    fn protects(&self, fault: CpuError) -> bool {
+        matches!(fault, CpuError::ReadOnly(_)) && self.read_only_code
+    }
+
+    /// Checks that the destinations of an instruction can be written once its operands are
+    /// read, failing with the first fault when the instruction has to stop on it. Handlers
+    /// check before any side effect, so that an instruction which stops does nothing
+    pub(crate) fn check_access(
+        &mut self,
+        halt_on_error: bool,
+        destinations: impl IntoIterator<Item = u8>,
+    ) -> Result<(), ErrorKind> {
+        for addr in destinations {
+            if let Err(err) = self.storable(addr) {
+                self.access_fault.get_or_insert(err);
+            }
+        }
+        match self.access_fault {
+            Some(fault) if halt_on_error || self.protects(fault) => Err(fault.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs an instruction through the handler of its opcode, then moves the program counter
+    fn execute(&mut self, [first, arg1, arg2]: [u8; 3]) -> Halted {
+        let handler = dispatch::HANDLERS[(first & 0b0000_1111) as usize];
+        let advance = match handler(self, first, arg1, arg2) {
+            Ok(advance) => advance,
+            Err(halted) => return halted,
+        };
+        match self.flat_mem {
+            Some(_) => self.set_pc(self.pc().wrapping_add_signed(advance)),
+            None => self.reg_zero = self.reg_zero.wrapping_add(advance as u8),
+        }
+        Halted::Running
+    }
+
+    /// How far a failed comparison moves the program counter: past the following instruction,
+    /// or past as many instructions as stored right after `arg2` when `skip_count` is set.
+    /// Skipping past the end of instruction memory stops at its last address, where the
+    /// program halts, instead of wrapping around
+    fn comparison_skip(&mut self, skip_count: bool, arg2: u8) -> i16 {
+        let skipped = if skip_count {
+            self.load(arg2.wrapping_add(1)) as i32
+        } else {
+            1
+        };
+        let last = match self.flat_mem {
+            Some(_) => u16::MAX,
+            None => u8::MAX as u16,
+        };
+        (Instruction::MAX_SIZE as i32 * (skipped + 1)).min((last - self.pc()) as i32) as i16
+    }
+
+    /// Whether instruction memory was made read only when building the CPU
+    pub fn read_only_code(&self) -> bool {
+        self.read_only_code
+    }
+
+    pub fn zero(&self) -> bool {
+        self.flags.contains(Flags::ZERO)
+    }
+
+    pub fn carry(&self) -> bool {
+        self.flags.contains(Flags::CARRY)
+    }
+
+    pub fn overflow(&self) -> bool {
+        self.flags.contains(Flags::OVERFLOW)
+    }
+
+    pub fn negative(&self) -> bool {
+        self.flags.contains(Flags::NEGATIVE)
+    }
+
+    /// Serializes the whole machine state: the registers, every memory bank and the state
+    /// of each device, in the order they are mapped
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = STATE_MAGIC.to_vec();
+        state.push(STATE_VERSION);
+        state.extend([
+            self.reg_zero,
+            self.flags.bits(),
+            self.sp,
+            self.inst_mem.pointer as u8,
+            self.data_mem.pointer as u8,
+        ]);
+        for bank in self.inst_mem.content.iter() {
+            state.extend(bank);
+        }
+        for bank in self.data_mem.content.iter() {
+            state.extend(bank);
+        }
+        state.push(self.devices.len() as u8);
+        for device in self.devices.iter() {
+            let snapshot = device.snapshot();
+            state.extend((snapshot.len() as u16).to_le_bytes());
+            state.extend(snapshot);
+        }
+        state
+    }
+
+    /// Restores a state returned by `save_state`. Nothing is modified if it is invalid
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let mut rest = bytes;
+        let mut take = |count: usize| {
+            if rest.len() < count {
+                return Err(StateError::Truncated);
             }
+            let (taken, remaining) = rest.split_at(count);
+            rest = remaining;
+            Ok(taken)
         };
-        self.reg_zero += 3;
+        if take(4)? != STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = take(1)?[0];
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let registers = take(5)?;
+        let inst_banks = take(256 * 127)?;
+        let data_banks = take(256 * 64)?;
+        let device_count = take(1)?[0] as usize;
+        if device_count != self.devices.len() {
+            return Err(StateError::DeviceCountMismatch {
+                expected: self.devices.len(),
+                found: device_count,
+            });
+        }
+        let mut snapshots = Vec::new();
+        for _ in 0..device_count {
+            let length = take(2)?;
+            snapshots.push(take(u16::from_le_bytes([length[0], length[1]]) as usize)?);
+        }
+
+        self.reg_zero = registers[0];
+        self.flags = Flags::from_bits(registers[1]);
+        self.sp = registers[2];
+        self.inst_mem.pointer = registers[3] as usize;
+        self.data_mem.pointer = registers[4] as usize;
+        for (bank, bytes) in self.inst_mem.content.iter_mut().zip(inst_banks.chunks(127)) {
+            bank.copy_from_slice(bytes);
+        }
+        self.invalidate_decode_cache();
+        for (bank, bytes) in self.data_mem.content.iter_mut().zip(data_banks.chunks(64)) {
+            bank.copy_from_slice(bytes);
+        }
+        for (device, snapshot) in self.devices.iter_mut().zip(snapshots) {
+            device.restore(snapshot);
+        }
+        Ok(())
+    }
+
+    /// Hexdump of the current data bank
+    pub fn dump_data(&self) -> String {
+        hexdump(
+            self.data_mem
+                .try_current_bank()
+                .map_or(&[], |bank| &bank[..]),
+        )
+    }
+
+    /// Hexdump of the current instruction bank
+    pub fn dump_inst(&self) -> String {
+        hexdump(
+            self.inst_mem
+                .try_current_bank()
+                .map_or(&[], |bank| &bank[..]),
+        )
+    }
+
+    /// Computes `op` on both values and stores the wrapped result in `addr`.
+    /// The stored bits do not depend on the signedness of the arguments, only the flags do:
+    /// CARRY is raised when the unsigned result wraps and OVERFLOW when the signed one does.
+    /// Other arithmetic modes use `signed` to tell whether the result fits, returning false
+    /// without storing anything when trapping
+    fn store_arithmetic(
+        &mut self,
+        signed: bool,
+        addr: u8,
+        data1: u8,
+        data2: u8,
+        op: impl Fn(i32, i32) -> i32,
+    ) -> bool {
+        let unsigned = op(data1 as i32, data2 as i32);
+        let signed_result = op(
+            i8::from_be_bytes([data1]) as i32,
+            i8::from_be_bytes([data2]) as i32,
+        );
+        let signed_range = i8::MIN as i32..=i8::MAX as i32;
+        let unsigned_range = u8::MIN as i32..=u8::MAX as i32;
+        let fits = if signed {
+            signed_range.contains(&signed_result)
+        } else {
+            unsigned_range.contains(&unsigned)
+        };
+        let result = match self.arith_mode {
+            ArithMode::Trapping if !fits => return false,
+            ArithMode::Saturating if !fits && signed => {
+                signed_result.clamp(i8::MIN as i32, i8::MAX as i32) as u8
+            }
+            ArithMode::Saturating if !fits => unsigned.clamp(0, u8::MAX as i32) as u8,
+            _ => unsigned as u8,
+        };
+        self.flags
+            .set(Flags::CARRY, !unsigned_range.contains(&unsigned));
+        self.flags
+            .set(Flags::OVERFLOW, !signed_range.contains(&signed_result));
+        self.store_result(addr, result);
+        true
+    }
+
+    /// Computes `op` on both arguments with their signedness applied and stores the result
+    /// in `arg1`. Fails if the instruction has to stop: on a division by zero, or on a result
+    /// that does not fit, signed if either argument is, when halting on errors. Otherwise the
+    /// division by zero is skipped and the result that does not fit wraps, setting the
+    /// overflow flag
+    fn division(
+        &mut self,
+        halt_on_error: bool,
+        sign1: bool,
+        sign2: bool,
+        arg1: u8,
+        arg2: u8,
+        op: fn(i32, i32) -> i32,
+    ) -> Result<(), ErrorKind> {
+        let data1 = self.load(arg1);
+        let data2 = self.load(arg2);
+        self.check_access(halt_on_error, [arg1])?;
+        if data2 == 0 {
+            return if halt_on_error {
+                Err(ErrorKind::DivByZero)
+            } else {
+                Ok(())
+            };
+        }
+        let result = op(extend(data1, sign1), extend(data2, sign2));
+        let overflow = if sign1 || sign2 {
+            i8::try_from(result).is_err()
+        } else {
+            u8::try_from(result).is_err()
+        };
+        if overflow && halt_on_error {
+            return Err(ErrorKind::Overflow);
+        }
+        self.flags.set(Flags::CARRY, false);
+        self.flags.set(Flags::OVERFLOW, overflow);
+        self.store_result(arg1, result as u8);
+        Ok(())
+    }
+
+    /// Copies a block of data memory starting at `src`. The destination address and the
+    /// length are stored in `control` and the address after it. Copies nothing if the block
+    /// does not fit in the data memory, which is an error when halting on errors
+    fn block_copy(&mut self, halt_on_error: bool, src: u8, control: u8) -> Result<(), ErrorKind> {
+        let dst = self.load(control);
+        let len = self.load(control.wrapping_add(1));
+        let fits = |start: u8| (128..=191).contains(&start) && start as usize + len as usize <= 192;
+        if !(1..=64).contains(&len) || !fits(src) || !fits(dst) {
+            self.check_access(halt_on_error, [])?;
+            return if halt_on_error {
+                Err(ErrorKind::BadBlockCopy)
+            } else {
+                Ok(())
+            };
+        }
+        let block: Vec<u8> = (src..src + len).map(|addr| self.load(addr)).collect();
+        self.check_access(halt_on_error, dst..dst + len)?;
+        for (addr, data) in (dst..dst + len).zip(block) {
+            self.push(addr, data);
+        }
+        Ok(())
+    }
+
+    /// Jumps to the vector of the first device requesting an interrupt, pushing the program
+    /// counter on the stack so the handler can return with Ret. Nothing happens while a
+    /// handler is running
+    fn interrupt(&mut self) -> Halted {
+        if self.handler_sp.is_some() {
+            return Halted::Running;
+        }
+        let vector = self
+            .devices
+            .iter()
+            .find_map(|device| device.interrupt_pending());
+        if let Some(vector) = vector {
+            if !self.stack_push(self.reg_zero) {
+                return Halted::Errored(ErrorKind::StackOverflow);
+            }
+            self.handler_sp = Some(self.sp);
+            self.reg_zero = vector;
+        }
         Halted::Running
     }
 
+    /// Whether an interrupt handler is running, which masks other interrupts
+    pub fn in_interrupt(&self) -> bool {
+        self.handler_sp.is_some()
+    }
+
+    /// Unmasks interrupts before the running handler returns, for handlers that are done
+    /// with their device and can be interrupted again
+    pub fn acknowledge_interrupt(&mut self) {
+        self.handler_sp = None;
+    }
+
+    /// Pops the return address of a Ret, which unmasks interrupts when it was pushed by one
+    pub(crate) fn pop_return(&mut self) -> Option<u8> {
+        let handler_returns = self.handler_sp == Some(self.sp);
+        let address = self.stack_pop()?;
+        if handler_returns {
+            self.handler_sp = None;
+        }
+        Some(address)
+    }
+
+    /// Returns false if the stack is full, or if the stack pointer is past its end
+    fn stack_push(&mut self, data: u8) -> bool {
+        if self.sp == 0 || self.sp > 64 {
+            return false;
+        }
+        self.sp -= 1;
+        self.in_stack_bank(|cpu| cpu.push(128 + cpu.sp, data));
+        true
+    }
+
+    /// Returns `None` if the stack is empty
+    fn stack_pop(&mut self) -> Option<u8> {
+        if self.sp >= 64 {
+            return None;
+        }
+        let data = self.in_stack_bank(|cpu| cpu.load(128 + cpu.sp));
+        self.sp += 1;
+        Some(data)
+    }
+
+    /// Runs a stack access in data bank 0, whichever bank is selected
+    fn in_stack_bank<R>(&mut self, access: impl FnOnce(&mut CPU) -> R) -> R {
+        let bank = core::mem::replace(&mut self.data_mem.pointer, 0);
+        let result = access(self);
+        self.data_mem.pointer = bank;
+        result
+    }
+
+    fn store_result(&mut self, addr: u8, data: u8) {
+        self.set_result_flags(data);
+        self.push(addr, data);
+    }
+
+    fn set_result_flags(&mut self, data: u8) {
+        self.flags.set(Flags::ZERO, data == 0);
+        self.flags.set(Flags::NEGATIVE, data & 0b1000_0000 != 0);
+    }
+
+    /// Reads any address the way instructions do, `None` if it cannot be read
+    pub fn read(&mut self, addr: u8) -> Option<u8> {
+        self.try_load(addr).ok()
+    }
+
+    /// Writes any address the way instructions do
+    pub fn write(&mut self, addr: u8, value: u8) -> Result<(), CpuError> {
+        self.try_push(addr, value)
+    }
+
     fn load(&mut self, addr: u8) -> u8 {
+        let value = self.try_load(addr).unwrap_or_else(|err| {
+            self.access_fault.get_or_insert(err);
+            0
+        });
+        if self.log_accesses {
+            self.access_log.push(AccessEvent::Load {
+                pc: self.exec_pc,
+                addr,
+                value,
+            });
+        }
+        value
+    }
+
+    /// Reads an address, failing when it maps to a device slot with nothing plugged in or past
+    /// the end of a memory bank
+    pub fn try_load(&mut self, addr: u8) -> Result<u8, CpuError> {
         match addr {
-            0 => self.reg_zero,
-            1..=127 => self.inst_mem[addr as usize],
-            128..=191 => self.data_mem[(addr - 128) as usize],
-            192 => self.inst_mem.pointer,
-            193 => self.data_mem.pointer,
-            _ => panic!("Invalid address: {}", addr),
+            FLAGS_ADDR => Ok(self.flags.bits()),
+            DEVICE_BASE..=255 => match self.device_at_mut(addr) {
+                Some(device) => Ok(device.load(addr)),
+                None => Err(CpuError::UnmappedDevice(addr)),
+            },
+            _ => self.peek(addr).ok_or(CpuError::OutOfBounds(addr)),
+        }
+    }
+
+    /// The device mapped to an address, `None` for an empty slot or an address below
+    /// `DEVICE_BASE`
+    pub fn device_at(&self, addr: u8) -> Option<&dyn Device> {
+        let index = self.device_index(addr)?;
+        Some(self.devices[index].as_ref())
+    }
+
+    pub fn device_at_mut(&mut self, addr: u8) -> Option<&mut dyn Device> {
+        let index = self.device_index(addr)?;
+        Some(self.devices[index].as_mut())
+    }
+
+    fn device_index(&self, addr: u8) -> Option<usize> {
+        self.device_map[addr.checked_sub(DEVICE_BASE)? as usize]
+    }
+
+    /// Offset in flat memory of the page the program counter is in
+    fn page_start(&self) -> usize {
+        (self.inst_mem.pointer as u8 as usize) << 8
+    }
+
+    /// Reads an address that is not mapped to a device, without any side effect
+    fn peek(&self, addr: u8) -> Option<u8> {
+        match addr {
+            0 => Some(self.reg_zero),
+            1..=127 => match &self.flat_mem {
+                Some(flat_mem) => Some(flat_mem[self.page_start() + addr as usize]),
+                None => self.inst_mem.try_get(addr as usize),
+            },
+            128..=191 => self.data_mem.try_get((addr - 128) as usize),
+            INST_PTR_ADDR => Some(self.inst_mem.pointer as u8),
+            DATA_PTR_ADDR => Some(self.data_mem.pointer as u8),
+            FLAGS_ADDR => Some(self.flags.bits()),
+            DEVICE_BASE..=255 => None,
+        }
+    }
+
+    /// Whether `try_push` would accept a write to `addr`, without any side effect
+    fn storable(&self, addr: u8) -> Result<(), CpuError> {
+        let in_bounds = match addr {
+            1..=127 if !self.allow_self_modify || self.read_only_code => {
+                return Err(CpuError::ReadOnly(addr))
+            }
+            1..=127 => {
+                self.flat_mem.is_some() || self.inst_mem.try_get::<u8>(addr as usize).is_some()
+            }
+            128..=191 => self.data_mem.try_get::<u8>((addr - 128) as usize).is_some(),
+            FLAGS_ADDR => true,
+            DEVICE_BASE..=255 if self.device_index(addr).is_none() => {
+                return Err(CpuError::UnmappedDevice(addr))
+            }
+            _ => true,
+        };
+        if in_bounds {
+            Ok(())
+        } else {
+            Err(CpuError::OutOfBounds(addr))
         }
     }
 
     fn push(&mut self, addr: u8, data: u8) {
-        println!("From push(): addr:{}, data:{}", addr, data);
+        if let Err(err) = self.try_push(addr, data) {
+            self.access_fault.get_or_insert(err);
+        } else if self.log_accesses {
+            self.access_log.push(AccessEvent::Store {
+                pc: self.exec_pc,
+                addr,
+                value: data,
+            });
+        }
+    }
+
+    /// Writes an address, failing when it maps to a device slot with nothing plugged in, past
+    /// the end of a memory bank, or to instruction memory while self modification is not
+    /// allowed or the code is read only
+    pub fn try_push(&mut self, addr: u8, data: u8) -> Result<(), CpuError> {
+        self.storable(addr)?;
+        let watched = if self.watchpoints.contains(&addr) {
+            self.peek(addr)
+        } else {
+            None
+        };
         match addr {
             0 => self.reg_zero = data,
-            1..=127 => self.inst_mem[addr as usize] = data,
-            128..=191 => self.data_mem[(addr - 128) as usize] = data,
-            192 => self.inst_mem.pointer = data,
-            193 => self.data_mem.pointer = data,
-            _ => panic!("Invalid address: {}", addr),
+            1..=127 => {
+                let page_start = self.page_start();
+                match &mut self.flat_mem {
+                    Some(flat_mem) => flat_mem[page_start + addr as usize] = data,
+                    None => self
+                        .inst_mem
+                        .try_set(addr as usize, data)
+                        .ok_or(CpuError::OutOfBounds(addr))?,
+                }
+                if let Some(cache) = &mut self.decode_cache {
+                    // Every instruction containing the byte
+                    for pc in addr.saturating_sub(2)..=addr {
+                        if let Some(entry) = cache.get_mut(pc as usize) {
+                            *entry = None;
+                        }
+                    }
+                }
+            }
+            128..=191 => self
+                .data_mem
+                .try_set((addr - 128) as usize, data)
+                .ok_or(CpuError::OutOfBounds(addr))?,
+            INST_PTR_ADDR => {
+                self.inst_mem.pointer = data as usize;
+                self.invalidate_decode_cache();
+            }
+            DATA_PTR_ADDR => self.data_mem.pointer = data as usize,
+            FLAGS_ADDR => self.flags = Flags::from_bits(data),
+            DEVICE_BASE..=255 => match self.device_at_mut(addr) {
+                Some(device) => device.push(addr, data),
+                None => return Err(CpuError::UnmappedDevice(addr)),
+            },
+        }
+        // Only writes that went through are recorded
+        if let Some(pc) = self.debug_pc {
+            self.debug_log.push(DebugRecord { pc, addr, data });
+        }
+        if let Some(old) = watched {
+            self.watch_log.push(WatchRecord {
+                pc: self.exec_pc,
+                addr,
+                old,
+                new: data,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Formats bytes like `hexdump -C`: 16 bytes per row after the offset, followed by their
+/// printable ASCII characters
+fn hexdump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (row, chunk) in bytes.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for (i, byte) in chunk.iter().enumerate() {
+            if i == 8 {
+                hex.push(' ');
+            }
+            hex += &format!(" {:02x}", byte);
         }
+        let ascii: String = chunk
+            .iter()
+            .map(|&byte| match byte {
+                0x20..=0x7e => byte as char,
+                _ => '.',
+            })
+            .collect();
+        dump += &format!("{:08x} {:<49}  |{}|\n", row * 16, hex, ascii);
+    }
+    dump
+}
+
+/// Widens a byte to the value it represents as a signed or unsigned argument
+fn extend(data: u8, signed: bool) -> i32 {
+    if signed {
+        i8::from_be_bytes([data]) as i32
+    } else {
+        data as i32
     }
 }
 
+/// Runs a single instruction on a fresh CPU without devices, returning the resulting state
+/// and the first memory access error it ran into. Meant for fuzzing: no input may panic
+pub fn execute_triple(bytes: [u8; 3]) -> (Halted, Option<CpuError>) {
+    let mut image = [0; 127];
+    image[..3].copy_from_slice(&bytes);
+    let mut cpu = CPU::new(image, Vec::new()).expect("a CPU without devices is always valid");
+    let halted = cpu.tick();
+    (halted, cpu.access_fault)
+}
+
+pub fn disassemble(image: &[u8; 127]) -> Vec<(u8, Instruction)> {
+    let mut program = Vec::new();
+    let mut pc = 0;
+    while pc + Instruction::MAX_SIZE as usize <= image.len() {
+        let inst = Instruction::from_3bytes([image[pc], image[pc + 1], image[pc + 2]]);
+        program.push((pc as u8, inst));
+        pc += inst.size() as usize;
+    }
+    program
+}
+
 pub trait Device {
     fn load(&mut self, addr: u8) -> u8;
     fn push(&mut self, addr: u8, data: u8);
     fn address(&self) -> u8;
+    /// Copies the device along with its current state
+    fn clone_box(&self) -> Box<dyn Device>;
+    /// Every address the device is mapped to, it receives the raw address on each access
+    fn address_range(&self) -> RangeInclusive<u8> {
+        let address = self.address();
+        address..=address
+    }
+    /// Called when the CPU is reset, to clear any internal state
+    fn reset(&mut self) {}
+    /// Called once every `clock_divider` executed instructions, after the last one ran
+    fn tick(&mut self) {}
+    /// How many instructions run for every device tick, 0 is treated like 1
+    fn clock_divider(&self) -> u32 {
+        1
+    }
+    /// Polled after every tick, returning the address of the handler to jump to. The
+    /// request stays pending until the device stops returning it
+    fn interrupt_pending(&self) -> Option<u8> {
+        None
+    }
+    /// Internal state of the device, to be saved along with the CPU
+    fn snapshot(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    /// Restores a state previously returned by `snapshot`
+    fn restore(&mut self, _data: &[u8]) {}
+}
+
+/// The 4 bits at the end of the first byte of an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    NoOp,
+    And,
+    Or,
+    /// The second argument selects the operation
+    Unary,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Shift,
+    Mod,
+    CompGe,
+    CompLe,
+    Copy,
+    CompEq,
+    CompGt,
+    CompLt,
 }
 
-#[derive(Debug)]
+impl Opcode {
+    const ALL: [Opcode; 16] = [
+        Opcode::NoOp,
+        Opcode::And,
+        Opcode::Or,
+        Opcode::Unary,
+        Opcode::Add,
+        Opcode::Sub,
+        Opcode::Mul,
+        Opcode::Div,
+        Opcode::Shift,
+        Opcode::Mod,
+        Opcode::CompGe,
+        Opcode::CompLe,
+        Opcode::Copy,
+        Opcode::CompEq,
+        Opcode::CompGt,
+        Opcode::CompLt,
+    ];
+
+    pub fn from_nibble(nibble: u8) -> Option<Opcode> {
+        Opcode::ALL.get(nibble as usize).copied()
+    }
+
+    pub fn to_nibble(self) -> u8 {
+        self as u8
+    }
+}
+
+/// The bits of the first byte of an instruction that are not part of the opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InstFlags {
+    pub halt_on_error: bool,
+    pub store_debug_info: bool,
+    pub arg1_signed: bool,
+    pub arg2_signed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Instruction {
     NoOp(bool, bool, bool, bool),
+    Halt(bool, bool, bool, bool),
     And(bool, bool, bool, bool, u8, u8),
+    Nand(bool, bool, bool, bool, u8, u8),
+    BitTest(bool, bool, bool, bool, u8, u8),
     Or(bool, bool, bool, bool, u8, u8),
+    Xor(bool, bool, bool, bool, u8, u8),
+    Nor(bool, bool, bool, bool, u8, u8),
     Not(bool, bool, bool, bool, u8),
     Add(bool, bool, bool, bool, u8, u8),
+    AddC(bool, bool, bool, bool, u8, u8),
     Sub(bool, bool, bool, bool, u8, u8),
     Mul(bool, bool, bool, bool, u8, u8),
     Div(bool, bool, bool, bool, u8, u8),
-    SL(bool, bool, bool, bool, u8),
-    SR(bool, bool, bool, bool, u8),
-    RL(bool, bool, bool, bool, u8),
-    RR(bool, bool, bool, bool, u8),
+    Mod(bool, bool, bool, bool, u8, u8),
+    SL(bool, bool, bool, bool, u8, u8),
+    SR(bool, bool, bool, bool, u8, u8),
+    RL(bool, bool, bool, bool, u8, u8),
+    RR(bool, bool, bool, bool, u8, u8),
+    SRA(bool, bool, bool, bool, u8),
+    Inc(bool, bool, bool, bool, u8),
+    Dec(bool, bool, bool, bool, u8),
+    Neg(bool, bool, bool, bool, u8),
+    Jmp(bool, bool, bool, bool, u8),
+    JmpRel(bool, bool, bool, bool, u8),
+    Call(bool, bool, bool, bool, u8),
+    Ret(bool, bool, bool, bool),
+    PopCount(bool, bool, bool, bool, u8),
+    Clz(bool, bool, bool, bool, u8),
+    BankInst(bool, bool, bool, bool, u8),
+    BankData(bool, bool, bool, bool, u8),
+    PushCtx(bool, bool, bool, bool),
+    PopCtx(bool, bool, bool, bool),
+    Clc(bool, bool, bool, bool),
+    Sec(bool, bool, bool, bool),
     Copy(bool, bool, bool, bool, u8, u8),
+    LoadImm(bool, bool, bool, bool, u8, u8),
+    Swap(bool, bool, bool, bool, u8, u8),
+    BlockCopy(bool, bool, bool, bool, u8, u8),
     CompEq(bool, bool, bool, bool, u8, u8),
+    CompNe(bool, bool, bool, bool, u8, u8),
+    CMovZ(bool, bool, bool, bool, u8, u8),
+    CMovNz(bool, bool, bool, bool, u8, u8),
     CompGt(bool, bool, bool, bool, u8, u8),
     CompLt(bool, bool, bool, bool, u8, u8),
+    CompGe(bool, bool, bool, bool, u8, u8),
+    CompLe(bool, bool, bool, bool, u8, u8),
 }
 
 impl Instruction {
+    pub fn opcode(&self) -> Opcode {
+        Opcode::ALL[(self.to_3bytes()[0] & 0b0000_1111) as usize]
+    }
+
+    pub fn flags(&self) -> InstFlags {
+        let (_, halt_on_error, store_debug_info, arg1_signed, arg2_signed, _) = self.parts();
+        InstFlags {
+            halt_on_error,
+            store_debug_info,
+            arg1_signed,
+            arg2_signed,
+        }
+    }
+
+    /// Length of the longest encoding, the most bytes to fetch before decoding
+    pub const MAX_SIZE: u8 = 3;
+
+    /// Length of the encoded instruction in bytes, which is always `MAX_SIZE` for now
+    pub fn size(&self) -> u8 {
+        Instruction::MAX_SIZE
+    }
+
+    /// The arguments the instruction uses, the literal of LoadImm included
+    pub fn operands(&self) -> (Option<u8>, Option<u8>) {
+        let args = self.parts().5;
+        (args.first().copied(), args.get(1).copied())
+    }
+
     pub fn from_3bytes(bytes: [u8; 3]) -> Instruction {
         let opcode = bytes[0] & 0b0000_1111;
         let halt_on_error = bytes[0] & 0b1000_0000 == 0b1000_0000;
         let store_debug_info = bytes[0] & 0b0100_0000 == 0b0100_0000;
         let arg1_signed = bytes[0] & 0b0010_0000 == 0b0010_0000;
         let arg2_signed = bytes[0] & 0b0001_0000 == 0b0001_0000;
+        // Opcodes which never treat their arguments as signed reuse the sign bits
+        // to select between operations
+        let function = (bytes[0] & 0b0011_0000) >> 4;
         let arg1 = bytes[1];
         let arg2 = bytes[2];
 
         match opcode {
             0 => Instruction::NoOp(halt_on_error, store_debug_info, arg1_signed, arg2_signed),
-            1 => Instruction::And(
-                halt_on_error,
-                store_debug_info,
-                arg1_signed,
-                arg2_signed,
-                arg1,
-                arg2,
-            ),
-            2 => Instruction::Or(
-                halt_on_error,
-                store_debug_info,
-                arg1_signed,
-                arg2_signed,
-                arg1,
-                arg2,
-            ),
-            3 => Instruction::Not(
-                halt_on_error,
-                store_debug_info,
-                arg1_signed,
-                arg2_signed,
-                arg1,
-            ),
+            1 => match function {
+                1 => Instruction::Nand(halt_on_error, store_debug_info, false, false, arg1, arg2),
+                2 => {
+                    Instruction::BitTest(halt_on_error, store_debug_info, false, false, arg1, arg2)
+                }
+                3 => Instruction::AddC(halt_on_error, store_debug_info, false, false, arg1, arg2),
+                _ => Instruction::And(halt_on_error, store_debug_info, false, false, arg1, arg2),
+            },
+            2 => match function {
+                1 => Instruction::Xor(halt_on_error, store_debug_info, false, false, arg1, arg2),
+                2 => Instruction::Nor(halt_on_error, store_debug_info, false, false, arg1, arg2),
+                _ => Instruction::Or(halt_on_error, store_debug_info, false, false, arg1, arg2),
+            },
+            3 => match arg2 {
+                1 => Instruction::Halt(halt_on_error, store_debug_info, arg1_signed, arg2_signed),
+                2 => Instruction::SRA(
+                    halt_on_error,
+                    store_debug_info,
+                    arg1_signed,
+                    arg2_signed,
+                    arg1,
+                ),
+                3 => Instruction::Inc(
+                    halt_on_error,
+                    store_debug_info,
+                    arg1_signed,
+                    arg2_signed,
+                    arg1,
+                ),
+                4 => Instruction::Dec(
+                    halt_on_error,
+                    store_debug_info,
+                    arg1_signed,
+                    arg2_signed,
+                    arg1,
+                ),
+                5 => Instruction::Neg(
+                    halt_on_error,
+                    store_debug_info,
+                    arg1_signed,
+                    arg2_signed,
+                    arg1,
+                ),
+                6 => Instruction::Jmp(
+                    halt_on_error,
+                    store_debug_info,
+                    arg1_signed,
+                    arg2_signed,
+                    arg1,
+                ),
+                7 => Instruction::JmpRel(
+                    halt_on_error,
+                    store_debug_info,
+                    arg1_signed,
+                    arg2_signed,
+                    arg1,
+                ),
+                8 => Instruction::Call(
+                    halt_on_error,
+                    store_debug_info,
+                    arg1_signed,
+                    arg2_signed,
+                    arg1,
+                ),
+                9 => Instruction::Ret(halt_on_error, store_debug_info, arg1_signed, arg2_signed),
+                14 => {
+                    Instruction::PushCtx(halt_on_error, store_debug_info, arg1_signed, arg2_signed)
+                }
+                15 => {
+                    Instruction::PopCtx(halt_on_error, store_debug_info, arg1_signed, arg2_signed)
+                }
+                16 => Instruction::Clc(halt_on_error, store_debug_info, arg1_signed, arg2_signed),
+                17 => Instruction::Sec(halt_on_error, store_debug_info, arg1_signed, arg2_signed),
+                10 => Instruction::PopCount(
+                    halt_on_error,
+                    store_debug_info,
+                    arg1_signed,
+                    arg2_signed,
+                    arg1,
+                ),
+                11 => Instruction::Clz(
+                    halt_on_error,
+                    store_debug_info,
+                    arg1_signed,
+                    arg2_signed,
+                    arg1,
+                ),
+                12 => Instruction::BankInst(
+                    halt_on_error,
+                    store_debug_info,
+                    arg1_signed,
+                    arg2_signed,
+                    arg1,
+                ),
+                13 => Instruction::BankData(
+                    halt_on_error,
+                    store_debug_info,
+                    arg1_signed,
+                    arg2_signed,
+                    arg1,
+                ),
+                _ => Instruction::Not(
+                    halt_on_error,
+                    store_debug_info,
+                    arg1_signed,
+                    arg2_signed,
+                    arg1,
+                ),
+            },
             4 => Instruction::Add(
                 halt_on_error,
                 store_debug_info,
@@ -345,35 +1776,13 @@ impl Instruction {
                 arg1,
                 arg2,
             ),
-            8 => Instruction::SL(
-                halt_on_error,
-                store_debug_info,
-                arg1_signed,
-                arg2_signed,
-                arg1,
-            ),
-            9 => Instruction::SR(
-                halt_on_error,
-                store_debug_info,
-                arg1_signed,
-                arg2_signed,
-                arg1,
-            ),
-            10 => Instruction::RL(
-                halt_on_error,
-                store_debug_info,
-                arg1_signed,
-                arg2_signed,
-                arg1,
-            ),
-            11 => Instruction::RR(
-                halt_on_error,
-                store_debug_info,
-                arg1_signed,
-                arg2_signed,
-                arg1,
-            ),
-            12 => Instruction::Copy(
+            8 => match function {
+                0 => Instruction::SL(halt_on_error, store_debug_info, false, false, arg1, arg2),
+                1 => Instruction::SR(halt_on_error, store_debug_info, false, false, arg1, arg2),
+                2 => Instruction::RL(halt_on_error, store_debug_info, false, false, arg1, arg2),
+                _ => Instruction::RR(halt_on_error, store_debug_info, false, false, arg1, arg2),
+            },
+            9 => Instruction::Mod(
                 halt_on_error,
                 store_debug_info,
                 arg1_signed,
@@ -381,7 +1790,7 @@ impl Instruction {
                 arg1,
                 arg2,
             ),
-            13 => Instruction::CompEq(
+            10 => Instruction::CompGe(
                 halt_on_error,
                 store_debug_info,
                 arg1_signed,
@@ -389,7 +1798,7 @@ impl Instruction {
                 arg1,
                 arg2,
             ),
-            14 => Instruction::CompGt(
+            11 => Instruction::CompLe(
                 halt_on_error,
                 store_debug_info,
                 arg1_signed,
@@ -397,7 +1806,28 @@ impl Instruction {
                 arg1,
                 arg2,
             ),
-            15 => Instruction::CompLt(
+            12 => match function {
+                1 => {
+                    Instruction::LoadImm(halt_on_error, store_debug_info, false, false, arg1, arg2)
+                }
+                2 => Instruction::Swap(halt_on_error, store_debug_info, false, false, arg1, arg2),
+                3 => Instruction::BlockCopy(
+                    halt_on_error,
+                    store_debug_info,
+                    false,
+                    false,
+                    arg1,
+                    arg2,
+                ),
+                _ => Instruction::Copy(halt_on_error, store_debug_info, false, false, arg1, arg2),
+            },
+            13 => match function {
+                1 => Instruction::CompNe(halt_on_error, store_debug_info, false, false, arg1, arg2),
+                2 => Instruction::CMovZ(halt_on_error, store_debug_info, false, false, arg1, arg2),
+                3 => Instruction::CMovNz(halt_on_error, store_debug_info, false, false, arg1, arg2),
+                _ => Instruction::CompEq(halt_on_error, store_debug_info, false, false, arg1, arg2),
+            },
+            14 => Instruction::CompGt(
                 halt_on_error,
                 store_debug_info,
                 arg1_signed,
@@ -405,13 +1835,237 @@ impl Instruction {
                 arg1,
                 arg2,
             ),
-            _ => panic!("Invalid opcode (This should never ever happen)"),
+            _ => {
+                debug_assert_eq!(opcode, 15);
+                Instruction::CompLt(
+                    halt_on_error,
+                    store_debug_info,
+                    arg1_signed,
+                    arg2_signed,
+                    arg1,
+                    arg2,
+                )
+            }
+        }
+    }
+
+    pub fn to_3bytes(&self) -> [u8; 3] {
+        let (opcode, halt_on_error, store_debug_info, arg1_signed, arg2_signed, arg1, arg2) =
+            match *self {
+                Instruction::NoOp(h, d, s1, s2) => (0, h, d, s1, s2, 0, 0),
+                Instruction::Halt(h, d, s1, s2) => (3, h, d, s1, s2, 0, 1),
+                Instruction::And(h, d, _, _, a1, a2) => (1, h, d, false, false, a1, a2),
+                Instruction::Nand(h, d, _, _, a1, a2) => (1, h, d, false, true, a1, a2),
+                Instruction::BitTest(h, d, _, _, a1, a2) => (1, h, d, true, false, a1, a2),
+                Instruction::AddC(h, d, _, _, a1, a2) => (1, h, d, true, true, a1, a2),
+                Instruction::Or(h, d, _, _, a1, a2) => (2, h, d, false, false, a1, a2),
+                Instruction::Xor(h, d, _, _, a1, a2) => (2, h, d, false, true, a1, a2),
+                Instruction::Nor(h, d, _, _, a1, a2) => (2, h, d, true, false, a1, a2),
+                Instruction::Not(h, d, s1, s2, a1) => (3, h, d, s1, s2, a1, 0),
+                Instruction::Add(h, d, s1, s2, a1, a2) => (4, h, d, s1, s2, a1, a2),
+                Instruction::Sub(h, d, s1, s2, a1, a2) => (5, h, d, s1, s2, a1, a2),
+                Instruction::Mul(h, d, s1, s2, a1, a2) => (6, h, d, s1, s2, a1, a2),
+                Instruction::Div(h, d, s1, s2, a1, a2) => (7, h, d, s1, s2, a1, a2),
+                Instruction::Mod(h, d, s1, s2, a1, a2) => (9, h, d, s1, s2, a1, a2),
+                Instruction::SL(h, d, _, _, a1, a2) => (8, h, d, false, false, a1, a2),
+                Instruction::SR(h, d, _, _, a1, a2) => (8, h, d, false, true, a1, a2),
+                Instruction::RL(h, d, _, _, a1, a2) => (8, h, d, true, false, a1, a2),
+                Instruction::RR(h, d, _, _, a1, a2) => (8, h, d, true, true, a1, a2),
+                Instruction::SRA(h, d, s1, s2, a1) => (3, h, d, s1, s2, a1, 2),
+                Instruction::Inc(h, d, s1, s2, a1) => (3, h, d, s1, s2, a1, 3),
+                Instruction::Dec(h, d, s1, s2, a1) => (3, h, d, s1, s2, a1, 4),
+                Instruction::Neg(h, d, s1, s2, a1) => (3, h, d, s1, s2, a1, 5),
+                Instruction::Jmp(h, d, s1, s2, a1) => (3, h, d, s1, s2, a1, 6),
+                Instruction::JmpRel(h, d, s1, s2, a1) => (3, h, d, s1, s2, a1, 7),
+                Instruction::Call(h, d, s1, s2, a1) => (3, h, d, s1, s2, a1, 8),
+                Instruction::Ret(h, d, s1, s2) => (3, h, d, s1, s2, 0, 9),
+                Instruction::PopCount(h, d, s1, s2, a1) => (3, h, d, s1, s2, a1, 10),
+                Instruction::Clz(h, d, s1, s2, a1) => (3, h, d, s1, s2, a1, 11),
+                Instruction::BankInst(h, d, s1, s2, a1) => (3, h, d, s1, s2, a1, 12),
+                Instruction::BankData(h, d, s1, s2, a1) => (3, h, d, s1, s2, a1, 13),
+                Instruction::PushCtx(h, d, s1, s2) => (3, h, d, s1, s2, 0, 14),
+                Instruction::PopCtx(h, d, s1, s2) => (3, h, d, s1, s2, 0, 15),
+                Instruction::Clc(h, d, s1, s2) => (3, h, d, s1, s2, 0, 16),
+                Instruction::Sec(h, d, s1, s2) => (3, h, d, s1, s2, 0, 17),
+                Instruction::Copy(h, d, _, _, a1, a2) => (12, h, d, false, false, a1, a2),
+                Instruction::LoadImm(h, d, _, _, a1, a2) => (12, h, d, false, true, a1, a2),
+                Instruction::Swap(h, d, _, _, a1, a2) => (12, h, d, true, false, a1, a2),
+                Instruction::BlockCopy(h, d, _, _, a1, a2) => (12, h, d, true, true, a1, a2),
+                Instruction::CompEq(h, d, _, _, a1, a2) => (13, h, d, false, false, a1, a2),
+                Instruction::CompNe(h, d, _, _, a1, a2) => (13, h, d, false, true, a1, a2),
+                Instruction::CMovZ(h, d, _, _, a1, a2) => (13, h, d, true, false, a1, a2),
+                Instruction::CMovNz(h, d, _, _, a1, a2) => (13, h, d, true, true, a1, a2),
+                Instruction::CompGt(h, d, s1, s2, a1, a2) => (14, h, d, s1, s2, a1, a2),
+                Instruction::CompLt(h, d, s1, s2, a1, a2) => (15, h, d, s1, s2, a1, a2),
+                Instruction::CompGe(h, d, s1, s2, a1, a2) => (10, h, d, s1, s2, a1, a2),
+                Instruction::CompLe(h, d, s1, s2, a1, a2) => (11, h, d, s1, s2, a1, a2),
+            };
+        [
+            ((halt_on_error as u8) << 7)
+                | ((store_debug_info as u8) << 6)
+                | ((arg1_signed as u8) << 5)
+                | ((arg2_signed as u8) << 4)
+                | opcode,
+            arg1,
+            arg2,
+        ]
+    }
+
+    /// Mnemonic, flags and arguments, in the order they are written in assembly
+    fn parts(&self) -> (&'static str, bool, bool, bool, bool, Vec<u8>) {
+        match *self {
+            Instruction::NoOp(h, d, s1, s2) => ("NOOP", h, d, s1, s2, vec![]),
+            Instruction::Halt(h, d, s1, s2) => ("HALT", h, d, s1, s2, vec![]),
+            Instruction::And(h, d, s1, s2, a1, a2) => ("AND", h, d, s1, s2, vec![a1, a2]),
+            Instruction::Nand(h, d, s1, s2, a1, a2) => ("NAND", h, d, s1, s2, vec![a1, a2]),
+            Instruction::BitTest(h, d, s1, s2, a1, a2) => ("BITTEST", h, d, s1, s2, vec![a1, a2]),
+            Instruction::Or(h, d, s1, s2, a1, a2) => ("OR", h, d, s1, s2, vec![a1, a2]),
+            Instruction::Xor(h, d, s1, s2, a1, a2) => ("XOR", h, d, s1, s2, vec![a1, a2]),
+            Instruction::Nor(h, d, s1, s2, a1, a2) => ("NOR", h, d, s1, s2, vec![a1, a2]),
+            Instruction::Not(h, d, s1, s2, a1) => ("NOT", h, d, s1, s2, vec![a1]),
+            Instruction::Add(h, d, s1, s2, a1, a2) => ("ADD", h, d, s1, s2, vec![a1, a2]),
+            Instruction::AddC(h, d, s1, s2, a1, a2) => ("ADDC", h, d, s1, s2, vec![a1, a2]),
+            Instruction::Sub(h, d, s1, s2, a1, a2) => ("SUB", h, d, s1, s2, vec![a1, a2]),
+            Instruction::Mul(h, d, s1, s2, a1, a2) => ("MUL", h, d, s1, s2, vec![a1, a2]),
+            Instruction::Div(h, d, s1, s2, a1, a2) => ("DIV", h, d, s1, s2, vec![a1, a2]),
+            Instruction::Mod(h, d, s1, s2, a1, a2) => ("MOD", h, d, s1, s2, vec![a1, a2]),
+            Instruction::SL(h, d, s1, s2, a1, a2) => ("SL", h, d, s1, s2, vec![a1, a2]),
+            Instruction::SR(h, d, s1, s2, a1, a2) => ("SR", h, d, s1, s2, vec![a1, a2]),
+            Instruction::RL(h, d, s1, s2, a1, a2) => ("RL", h, d, s1, s2, vec![a1, a2]),
+            Instruction::RR(h, d, s1, s2, a1, a2) => ("RR", h, d, s1, s2, vec![a1, a2]),
+            Instruction::SRA(h, d, s1, s2, a1) => ("SRA", h, d, s1, s2, vec![a1]),
+            Instruction::Inc(h, d, s1, s2, a1) => ("INC", h, d, s1, s2, vec![a1]),
+            Instruction::Dec(h, d, s1, s2, a1) => ("DEC", h, d, s1, s2, vec![a1]),
+            Instruction::Neg(h, d, s1, s2, a1) => ("NEG", h, d, s1, s2, vec![a1]),
+            Instruction::Jmp(h, d, s1, s2, a1) => ("JMP", h, d, s1, s2, vec![a1]),
+            Instruction::JmpRel(h, d, s1, s2, a1) => ("JMPREL", h, d, s1, s2, vec![a1]),
+            Instruction::Call(h, d, s1, s2, a1) => ("CALL", h, d, s1, s2, vec![a1]),
+            Instruction::Ret(h, d, s1, s2) => ("RET", h, d, s1, s2, vec![]),
+            Instruction::PushCtx(h, d, s1, s2) => ("PUSHCTX", h, d, s1, s2, vec![]),
+            Instruction::PopCtx(h, d, s1, s2) => ("POPCTX", h, d, s1, s2, vec![]),
+            Instruction::Clc(h, d, s1, s2) => ("CLC", h, d, s1, s2, vec![]),
+            Instruction::Sec(h, d, s1, s2) => ("SEC", h, d, s1, s2, vec![]),
+            Instruction::PopCount(h, d, s1, s2, a1) => ("POPCOUNT", h, d, s1, s2, vec![a1]),
+            Instruction::Clz(h, d, s1, s2, a1) => ("CLZ", h, d, s1, s2, vec![a1]),
+            Instruction::BankInst(h, d, s1, s2, a1) => ("BANKINST", h, d, s1, s2, vec![a1]),
+            Instruction::BankData(h, d, s1, s2, a1) => ("BANKDATA", h, d, s1, s2, vec![a1]),
+            Instruction::Copy(h, d, s1, s2, a1, a2) => ("COPY", h, d, s1, s2, vec![a1, a2]),
+            Instruction::LoadImm(h, d, s1, s2, a1, a2) => ("LOADIMM", h, d, s1, s2, vec![a1, a2]),
+            Instruction::Swap(h, d, s1, s2, a1, a2) => ("SWAP", h, d, s1, s2, vec![a1, a2]),
+            Instruction::BlockCopy(h, d, s1, s2, a1, a2) => {
+                ("BLOCKCOPY", h, d, s1, s2, vec![a1, a2])
+            }
+            Instruction::CompEq(h, d, s1, s2, a1, a2) => ("COMPEQ", h, d, s1, s2, vec![a1, a2]),
+            Instruction::CompNe(h, d, s1, s2, a1, a2) => ("COMPNE", h, d, s1, s2, vec![a1, a2]),
+            Instruction::CMovZ(h, d, s1, s2, a1, a2) => ("CMOVZ", h, d, s1, s2, vec![a1, a2]),
+            Instruction::CMovNz(h, d, s1, s2, a1, a2) => ("CMOVNZ", h, d, s1, s2, vec![a1, a2]),
+            Instruction::CompGt(h, d, s1, s2, a1, a2) => ("COMPGT", h, d, s1, s2, vec![a1, a2]),
+            Instruction::CompLt(h, d, s1, s2, a1, a2) => ("COMPLT", h, d, s1, s2, vec![a1, a2]),
+            Instruction::CompGe(h, d, s1, s2, a1, a2) => ("COMPGE", h, d, s1, s2, vec![a1, a2]),
+            Instruction::CompLe(h, d, s1, s2, a1, a2) => ("COMPLE", h, d, s1, s2, vec![a1, a2]),
+        }
+    }
+
+    /// Whether the last argument is a literal value rather than an address
+    fn has_literal(&self) -> bool {
+        matches!(self, Instruction::LoadImm(..))
+    }
+
+    /// Whether the instruction skips the following ones when false, in which case its Store
+    /// debug info bit holds the skip count instead
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            Instruction::CompEq(..)
+                | Instruction::CompNe(..)
+                | Instruction::CompGt(..)
+                | Instruction::CompLt(..)
+                | Instruction::CompGe(..)
+                | Instruction::CompLe(..)
+        )
+    }
+
+    /// Every address the instruction reads from or writes to through its arguments
+    fn addresses(&self) -> Vec<u8> {
+        let mut args = self.parts().5;
+        if self.has_literal() {
+            args.pop();
+        }
+        args
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (mnemonic, halt_on_error, store_debug_info, arg1_signed, arg2_signed, args) =
+            self.parts();
+        write!(f, "{}", mnemonic)?;
+        for (set, suffix) in [
+            (arg1_signed, ".s1"),
+            (arg2_signed, ".s2"),
+            (halt_on_error, ".halt"),
+            (store_debug_info && !self.is_comparison(), ".debug"),
+            (store_debug_info && self.is_comparison(), ".skip"),
+        ] {
+            if set {
+                write!(f, "{}", suffix)?;
+            }
         }
+        let literal = self.has_literal().then(|| args.len() - 1);
+        for (i, addr) in args.into_iter().enumerate() {
+            write!(f, "{}", if i == 0 { " " } else { ", " })?;
+            if literal == Some(i) {
+                write!(f, "{}", addr)?;
+                continue;
+            }
+            match addr {
+                0..=127 => write!(f, "r{}", addr)?,
+                128..=191 => write!(f, "m{}", addr - 128)?,
+                INST_PTR_ADDR => write!(f, "ip")?,
+                DATA_PTR_ADDR => write!(f, "dp")?,
+                FLAGS_ADDR => write!(f, "flags")?,
+                DEVICE_BASE..=255 => write!(f, "dev{}", addr - DEVICE_BASE)?,
+            }
+        }
+        Ok(())
     }
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint(u8),
+    Halted,
+    Errored(ErrorKind),
+    StepLimit,
+}
+
+#[derive(Debug, PartialEq, Eq)]
 pub enum Halted {
     Running,
-    Errored,
+    Errored(ErrorKind),
     Halted,
 }
+
+/// Why an instruction stopped the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    DivByZero,
+    /// An arithmetic result that does not fit, when trapping or for a division
+    Overflow,
+    UnmappedDevice(u8),
+    SelfModifyBlocked(u8),
+    OutOfBounds(u8),
+    StackOverflow,
+    StackUnderflow,
+    BadBlockCopy,
+}
+
+impl From<CpuError> for ErrorKind {
+    fn from(error: CpuError) -> ErrorKind {
+        match error {
+            CpuError::UnmappedDevice(addr) => ErrorKind::UnmappedDevice(addr),
+            CpuError::ReadOnly(addr) => ErrorKind::SelfModifyBlocked(addr),
+            CpuError::OutOfBounds(addr) => ErrorKind::OutOfBounds(addr),
+        }
+    }
+}
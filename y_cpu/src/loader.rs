@@ -0,0 +1,72 @@
+use alloc::vec::Vec;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LoadError {
+    TooLarge(usize),
+}
+
+/// Copies a raw program into an instruction image, padding it with zeroes
+pub fn load_binary(bytes: &[u8]) -> Result<[u8; 127], LoadError> {
+    let mut image = [0; 127];
+    if bytes.len() > image.len() {
+        return Err(LoadError::TooLarge(bytes.len()));
+    }
+    image[..bytes.len()].copy_from_slice(bytes);
+    Ok(image)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum IhexError {
+    MissingStartCode { line: usize },
+    InvalidHex { line: usize },
+    BadLength { line: usize },
+    BadChecksum { line: usize },
+    UnsupportedRecord { line: usize, record_type: u8 },
+    AddressOutOfRange { line: usize, address: u16 },
+    MissingEof,
+}
+
+/// Parses the data and end of file records of an Intel HEX file into an instruction image
+pub fn load_ihex(source: &str) -> Result<[u8; 127], IhexError> {
+    let mut image = [0; 127];
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let hex = text
+            .strip_prefix(':')
+            .ok_or(IhexError::MissingStartCode { line })?;
+        if hex.len() % 2 != 0 || !hex.is_ascii() {
+            return Err(IhexError::InvalidHex { line });
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| IhexError::InvalidHex { line })?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(IhexError::BadLength { line });
+        }
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(IhexError::BadChecksum { line });
+        }
+
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]);
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            0x00 => {
+                let start = address as usize;
+                if start + data.len() > image.len() {
+                    let address = (start + data.len() - 1) as u16;
+                    return Err(IhexError::AddressOutOfRange { line, address });
+                }
+                image[start..start + data.len()].copy_from_slice(data);
+            }
+            0x01 => return Ok(image),
+            record_type => return Err(IhexError::UnsupportedRecord { line, record_type }),
+        }
+    }
+    Err(IhexError::MissingEof)
+}
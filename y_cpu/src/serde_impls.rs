@@ -0,0 +1,129 @@
+use alloc::vec::Vec;
+
+use serde::{
+    de::{self, Deserializer},
+    ser::{SerializeStruct, Serializer},
+    Deserialize, Serialize,
+};
+
+use crate::{ArithMode, Banker, Flags, CPU};
+
+/// Banks are written as a sequence of byte sequences, serde does not handle arrays that large
+impl<const M: usize, const N: usize> Serialize for Banker<[u8; M], N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let banks: Vec<&[u8]> = self.content.iter().map(|bank| &bank[..]).collect();
+        let mut state = serializer.serialize_struct("Banker", 2)?;
+        state.serialize_field("content", &banks)?;
+        state.serialize_field("pointer", &self.pointer)?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Banker")]
+struct BankerData {
+    content: Vec<Vec<u8>>,
+    pointer: usize,
+}
+
+impl<'de, const M: usize, const N: usize> Deserialize<'de> for Banker<[u8; M], N> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data = BankerData::deserialize(deserializer)?;
+        let banks = data
+            .content
+            .into_iter()
+            .map(|bank| {
+                <[u8; M]>::try_from(bank)
+                    .map_err(|bank| de::Error::invalid_length(bank.len(), &"a full bank"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let content = <[[u8; M]; N]>::try_from(banks)
+            .map_err(|banks| de::Error::invalid_length(banks.len(), &"every bank"))?;
+        Ok(Banker {
+            content,
+            pointer: data.pointer,
+        })
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename = "CPU")]
+struct CpuStateRef<'a> {
+    reg_zero: u8,
+    flags: Flags,
+    sp: u8,
+    inst_mem: &'a Banker<[u8; 127]>,
+    flat_mem: Option<&'a [u8]>,
+    data_mem: &'a Banker<[u8; 64]>,
+    interrupts_enabled: bool,
+    handler_sp: Option<u8>,
+    arith_mode: ArithMode,
+    allow_self_modify: bool,
+    read_only_code: bool,
+    cycles: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "CPU")]
+struct CpuState {
+    reg_zero: u8,
+    flags: Flags,
+    sp: u8,
+    inst_mem: Banker<[u8; 127]>,
+    flat_mem: Option<Vec<u8>>,
+    data_mem: Banker<[u8; 64]>,
+    interrupts_enabled: bool,
+    handler_sp: Option<u8>,
+    arith_mode: ArithMode,
+    allow_self_modify: bool,
+    read_only_code: bool,
+    cycles: u64,
+}
+
+/// Writes the registers, memory and settings of the CPU. Devices are trait objects so they are
+/// left out, along with logs, breakpoints and hooks: the deserialized CPU has no device, plug
+/// them back in with `CPU::add_device`
+impl Serialize for CPU {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        CpuStateRef {
+            reg_zero: self.reg_zero,
+            flags: self.flags,
+            sp: self.sp,
+            inst_mem: &self.inst_mem,
+            flat_mem: self.flat_mem.as_deref(),
+            data_mem: &self.data_mem,
+            interrupts_enabled: self.interrupts_enabled,
+            handler_sp: self.handler_sp,
+            arith_mode: self.arith_mode,
+            allow_self_modify: self.allow_self_modify,
+            read_only_code: self.read_only_code,
+            cycles: self.cycles,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CPU {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let state = CpuState::deserialize(deserializer)?;
+        if let Some(flat_mem) = &state.flat_mem {
+            if flat_mem.len() != 0x10000 {
+                return Err(de::Error::invalid_length(flat_mem.len(), &"65536 bytes"));
+            }
+        }
+        let mut cpu = CPU::new([0; 127], Vec::new()).expect("a CPU without devices always builds");
+        cpu.reg_zero = state.reg_zero;
+        cpu.flags = state.flags;
+        cpu.sp = state.sp;
+        cpu.inst_mem = state.inst_mem;
+        cpu.flat_mem = state.flat_mem;
+        cpu.data_mem = state.data_mem;
+        cpu.interrupts_enabled = state.interrupts_enabled;
+        cpu.handler_sp = state.handler_sp;
+        cpu.arith_mode = state.arith_mode;
+        cpu.allow_self_modify = state.allow_self_modify;
+        cpu.read_only_code = state.read_only_code;
+        cpu.cycles = state.cycles;
+        Ok(cpu)
+    }
+}
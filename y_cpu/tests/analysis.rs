@@ -0,0 +1,89 @@
+use y_cpu::{
+    analysis::{analyze, reachable_addrs, ProgramReport, Reachability},
+    assembler::assemble,
+};
+
+#[test]
+fn self_jump() {
+    let report = analyze(&assemble("loadimm m0, 3\njmp m0").unwrap());
+    assert_eq!(report.self_jumps, [3]);
+
+    // r0 reads the address of the jump itself
+    let report = analyze(&assemble("inc m0\njmp r0").unwrap());
+    assert_eq!(report.self_jumps, [3]);
+
+    // Only taken while m0 equals m1, which the program may change
+    let report = analyze(&assemble("loadimm m2, 6\ncompeq m0, m1\njmp m2\nhalt").unwrap());
+    assert_eq!(report, ProgramReport::default());
+}
+
+#[test]
+fn dead_code_after_halt() {
+    let report = analyze(&assemble("inc m0\nhalt\ninc m1\ninc m2").unwrap());
+    assert_eq!(report.unreachable, vec![6..12]);
+    assert!(report.self_jumps.is_empty());
+}
+
+#[test]
+fn skip_count() {
+    // Both halts may be skipped, to land on the increment
+    let program = assemble("loadimm m2, 2\ncompeq.skip m0, m1\nhalt\nhalt\ninc m3\nhalt").unwrap();
+    assert_eq!(analyze(&program), ProgramReport::default());
+    assert_eq!(
+        reachable_addrs(&program)
+            .reachable
+            .into_iter()
+            .collect::<Vec<_>>(),
+        [0, 3, 6, 12, 15]
+    );
+
+    // The count is not known, so any of the following instructions may be skipped to
+    let program =
+        assemble("compeq.skip m0, m1\nhalt\nhalt\ninc m3\nloadimm m4, 15\njmp m4").unwrap();
+    assert_eq!(analyze(&program), ProgramReport::default());
+}
+
+#[test]
+fn reachable_through_branch() {
+    let program = assemble(
+        "
+        compeq m0, m1
+        halt
+        inc m4
+        halt
+        inc m5
+    ",
+    )
+    .unwrap();
+    // 6 and 9 are only reached by skipping the first halt
+    assert_eq!(
+        reachable_addrs(&program)
+            .reachable
+            .into_iter()
+            .collect::<Vec<_>>(),
+        [0, 3, 6, 9]
+    );
+}
+
+#[test]
+fn dynamic_jumps() {
+    let program = assemble(
+        "
+        compeq.skip m1, m2
+        loadimm m0, 12
+        call m0
+        jmp m4
+        inc m5
+        ret
+    ",
+    )
+    .unwrap();
+    // The skip count in m3 and the target in m4 are not known
+    assert_eq!(
+        reachable_addrs(&program),
+        Reachability {
+            reachable: [0, 3, 6, 9, 12, 15].into(),
+            dynamic: [0, 9].into(),
+        }
+    );
+}
@@ -0,0 +1,215 @@
+use y_cpu::{assembler::assemble, ArithMode, ErrorKind, Halted, StopReason, CPU};
+
+/// Runs the raw `program` until it stops, with `data` at the start of data
+/// memory
+fn run(program: &[u8], data: &[u8]) -> (Halted, CPU) {
+    let mut inst_mem = [0; 127];
+    inst_mem[..program.len()].copy_from_slice(program);
+    let mut cpu = CPU::new(inst_mem, Vec::new()).unwrap();
+    cpu.data_mem[0..data.len()].copy_from_slice(data);
+    for _ in 0..100 {
+        let halted = cpu.tick();
+        if halted != Halted::Running {
+            return (halted, cpu);
+        }
+    }
+    panic!("the program did not stop");
+}
+
+/// Runs `source` until it stops
+fn run_source(source: &str) -> (Halted, CPU) {
+    let mut cpu = CPU::new(assemble(source).unwrap(), Vec::new()).unwrap();
+    let (halted, _) = cpu.run(20);
+    (halted, cpu)
+}
+
+/// Runs `source` with the CPU set to `mode`, returning how it stopped
+fn run_in(mode: ArithMode, source: &str) -> (Halted, CPU) {
+    let mut cpu = CPU::new(assemble(source).unwrap(), Vec::new()).unwrap();
+    cpu.arith_mode = mode;
+    let (halted, _) = cpu.run(20);
+    (halted, cpu)
+}
+
+#[test]
+fn div_by_zero() {
+    // DIV.halt and DIV.s1.s2.halt
+    for first in [0x87, 0xB7] {
+        let (halted, cpu) = run(&[first, 128, 129], &[0xF0]);
+        assert_eq!(halted, Halted::Errored(ErrorKind::DivByZero));
+        assert_eq!(cpu.data_mem[0], 0xF0);
+        assert_eq!(cpu.reg_zero, 0);
+    }
+}
+
+#[test]
+fn wrapping() {
+    // ADD m0, m1; SUB m2, m1; HALT
+    let (halted, cpu) = run(&[0x04, 128, 129, 0x05, 130, 129, 0x03, 0, 1], &[255, 1]);
+    assert_eq!(halted, Halted::Halted);
+    assert_eq!(cpu.data_mem[0], 0);
+    assert_eq!(cpu.data_mem[2], 255);
+
+    // MUL.s1.s2 m0, m1; HALT
+    let (halted, cpu) = run(&[0x36, 128, 129, 0x03, 0, 1], &[0x80, 0xFF]);
+    assert_eq!(halted, Halted::Halted);
+    assert_eq!(cpu.data_mem[0], i8::MIN as u8);
+}
+
+#[test]
+fn modulo() {
+    let (halted, cpu) = run_source("loadimm m0, 7\nloadimm m1, 3\nmod m0, m1\nhalt");
+    assert_eq!(halted, Halted::Halted);
+    assert_eq!(cpu.data_mem[0], 1);
+
+    // The remainder takes the sign of the dividend
+    let (_, cpu) = run_source("loadimm m0, 0xF9\nloadimm m1, 3\nmod.s1 m0, m1\nhalt");
+    assert_eq!(cpu.data_mem[0], -1i8 as u8);
+
+    let (halted, cpu) = run_source("loadimm m0, 7\nmod.halt m0, m1\nhalt");
+    assert_eq!(halted, Halted::Errored(ErrorKind::DivByZero));
+    assert_eq!(cpu.data_mem[0], 7);
+}
+
+#[test]
+fn inc_dec() {
+    let (_, cpu) = run_source("loadimm m0, 255\ninc m0\nhalt");
+    assert_eq!(cpu.data_mem[0], 0);
+    assert!(cpu.carry());
+    assert!(cpu.zero());
+
+    let (_, cpu) = run_source("dec m0\nhalt");
+    assert_eq!(cpu.data_mem[0], 255);
+}
+
+#[test]
+fn neg() {
+    for (value, negated) in [(1, 255), (0, 0), (0x80, 0x80)] {
+        let (halted, cpu) = run_source(&format!("loadimm m0, {value}\nneg m0\nhalt"));
+        assert_eq!(halted, Halted::Halted);
+        assert_eq!(cpu.data_mem[0], negated);
+        // Only -128 has no positive counterpart
+        assert_eq!(cpu.overflow(), value == 0x80);
+    }
+}
+
+#[test]
+fn halt_on_error() {
+    let (halted, cpu) = run_source("loadimm m0, 9\ndiv.halt m0, m1\ninc m2\nhalt");
+    assert_eq!(halted, Halted::Errored(ErrorKind::DivByZero));
+    assert_eq!(cpu.data_mem[2], 0);
+
+    // Without the flag the division is skipped and the program carries on
+    let (halted, cpu) = run_source("loadimm m0, 9\ndiv m0, m1\ninc m2\nhalt");
+    assert_eq!(halted, Halted::Halted);
+    assert_eq!(cpu.data_mem[0], 9);
+    assert_eq!(cpu.data_mem[2], 1);
+}
+
+#[test]
+fn faulting_instruction_has_no_effect() {
+    let (halted, cpu) = run_source("call.halt dev6\nhalt");
+    assert_eq!(halted, Halted::Errored(ErrorKind::UnmappedDevice(200)));
+    assert_eq!(cpu.pc(), 0);
+    assert_eq!(cpu.sp, 64);
+
+    // Neither the result nor the flags are stored
+    let (halted, cpu) = run_source("loadimm m0, 1\nadd.halt m0, dev6\nhalt");
+    assert_eq!(halted, Halted::Errored(ErrorKind::UnmappedDevice(200)));
+    assert_eq!(cpu.data_mem[0], 1);
+    assert_eq!(cpu.flags.bits(), 0);
+
+    // Swapping with a cell that cannot be written leaves the other one alone
+    let (halted, cpu) = run_source("loadimm m0, 1\nswap.halt m0, dev6\nhalt");
+    assert_eq!(halted, Halted::Errored(ErrorKind::UnmappedDevice(200)));
+    assert_eq!(cpu.data_mem[0], 1);
+
+    // BitTest does not write its first argument, even to protected code
+    let mut cpu = CPU::new(assemble("bittest.halt r3, r3\nhalt").unwrap(), Vec::new()).unwrap();
+    cpu.allow_self_modify = false;
+    assert_eq!(cpu.run(10).0, Halted::Halted);
+}
+
+#[test]
+fn error_payload() {
+    let source = "loadimm m0, 1\ndiv.halt m0, m1\nhalt";
+    let cpu = || CPU::new(assemble(source).unwrap(), Vec::new()).unwrap();
+    let mut stepped = cpu();
+    stepped.tick();
+    assert_eq!(stepped.step().1, Halted::Errored(ErrorKind::DivByZero));
+
+    assert_eq!(cpu().run(10), (Halted::Errored(ErrorKind::DivByZero), 2));
+    assert_eq!(
+        cpu().run_to_breakpoint(10),
+        StopReason::Errored(ErrorKind::DivByZero)
+    );
+}
+
+#[test]
+fn arith_modes() {
+    let add = "loadimm m0, 200\nloadimm m1, 100\nadd m0, m1\nhalt";
+
+    let (halted, mut cpu) = run_in(ArithMode::Wrapping, add);
+    assert_eq!(halted, Halted::Halted);
+    assert_eq!(cpu.read(128), Some(44));
+
+    let (halted, mut cpu) = run_in(ArithMode::Trapping, add);
+    assert_eq!(halted, Halted::Errored(ErrorKind::Overflow));
+    assert_eq!(cpu.read(128), Some(200));
+
+    let (halted, mut cpu) = run_in(ArithMode::Saturating, add);
+    assert_eq!(halted, Halted::Halted);
+    assert_eq!(cpu.read(128), Some(255));
+}
+
+#[test]
+fn neg_is_signed() {
+    // 200 is -56 as a signed value, it negates fine in every mode
+    for mode in [
+        ArithMode::Wrapping,
+        ArithMode::Trapping,
+        ArithMode::Saturating,
+    ] {
+        let (halted, mut cpu) = run_in(mode, "loadimm m0, 200\nneg m0\nhalt");
+        assert_eq!(halted, Halted::Halted);
+        assert_eq!(cpu.read(128), Some(56));
+    }
+
+    let neg_min = "loadimm m0, 0x80\nneg m0\nhalt";
+    let (halted, mut cpu) = run_in(ArithMode::Trapping, neg_min);
+    assert_eq!(halted, Halted::Errored(ErrorKind::Overflow));
+    assert_eq!(cpu.read(128), Some(0x80));
+    let (_, mut cpu) = run_in(ArithMode::Saturating, neg_min);
+    assert_eq!(cpu.read(128), Some(0x7F));
+}
+
+#[test]
+fn division_overflow() {
+    let (halted, cpu) = run_in(
+        ArithMode::Wrapping,
+        "loadimm m0, 0x80\nloadimm m1, 0xFF\ndiv.s1.s2 m0, m1\nhalt",
+    );
+    assert_eq!(halted, Halted::Halted);
+    assert_eq!(cpu.data_mem[0], 0x80);
+    assert!(cpu.overflow());
+
+    // -200 fits neither range
+    let (_, cpu) = run_in(
+        ArithMode::Wrapping,
+        "loadimm m0, 200\nloadimm m1, 0xFF\ndiv.s2 m0, m1\nhalt",
+    );
+    assert_eq!(cpu.data_mem[0], 56);
+    assert!(cpu.overflow());
+    let (halted, cpu) = run_in(
+        ArithMode::Wrapping,
+        "loadimm m0, 200\nloadimm m1, 0xFF\ndiv.s2.halt m0, m1\nhalt",
+    );
+    assert_eq!(halted, Halted::Errored(ErrorKind::Overflow));
+    assert_eq!(cpu.data_mem[0], 200);
+
+    let (_, cpu) = run_in(
+        ArithMode::Wrapping,
+        "loadimm m0, 200\nloadimm m1, 2\ndiv m0, m1\nhalt",
+    );
+    assert!(!cpu.overflow());
+}
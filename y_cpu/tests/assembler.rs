@@ -0,0 +1,149 @@
+use y_cpu::{
+    assembler::{assemble, AssembleError},
+    disassemble, Halted, Instruction, CPU,
+};
+
+#[test]
+fn disassembly_addresses() {
+    let program = disassemble(&[0; 127]);
+    let addrs: Vec<u8> = program.iter().map(|&(addr, _)| addr).collect();
+    // The last instruction that fits ends at 126
+    assert_eq!(addrs, (0..=123).step_by(3).collect::<Vec<u8>>());
+    assert!(program
+        .iter()
+        .all(|(_, inst)| matches!(inst, Instruction::NoOp(false, false, false, false))));
+}
+
+#[test]
+fn disassembly_round_trip() {
+    let source = "not m0\nadd.s1.halt m0, r5\ncopy m0, dev6";
+    let program = assemble(source).unwrap();
+    let lines: Vec<String> = disassemble(&program)
+        .iter()
+        .take(3)
+        .map(|(_, inst)| inst.to_string().to_lowercase())
+        .collect();
+    assert_eq!(lines.join("\n"), source);
+    assert_eq!(assemble(&lines.join("\n")), Ok(program));
+}
+
+#[test]
+fn byte_table() {
+    let program = assemble(
+        "
+        loadimm m0, table
+        halt
+    table:
+        .byte 1, 2, 0x2A
+    after:
+        .byte after
+        inc m0
+    ",
+    )
+    .unwrap();
+    // The literal of the loadimm
+    assert_eq!(program[2], 6);
+    assert_eq!(program[6..10], [1, 2, 0x2A, 9]);
+    // Instructions carry on right after the bytes
+    assert_eq!(program[10..13], assemble("inc m0").unwrap()[..3]);
+}
+
+#[test]
+fn org_overlap() {
+    let program = assemble(".org 9\n.byte 7\n.org 3\ninc m0\nhalt").unwrap();
+    assert_eq!(program[3..10], [0x03, 128, 3, 0x03, 0, 1, 7]);
+
+    assert_eq!(
+        assemble("inc m0\ninc m1\n.org 4\n.byte 7"),
+        Err(AssembleError::Overlap {
+            line: 4,
+            earlier_line: 2
+        })
+    );
+}
+
+#[test]
+fn comparison_suffixes() {
+    assert!(assemble("compeq.skip m0, m1").is_ok());
+    assert_eq!(
+        assemble("halt\ninc.skip m0"),
+        Err(AssembleError::SuffixNotAllowed {
+            line: 2,
+            suffix: "skip".to_owned()
+        })
+    );
+    // The debug bit is the skip count bit of comparisons
+    assert_eq!(
+        assemble("compgt.debug m0, m1"),
+        Err(AssembleError::SuffixNotAllowed {
+            line: 1,
+            suffix: "debug".to_owned()
+        })
+    );
+}
+
+#[test]
+fn countdown_loop() {
+    let program = assemble(
+        "
+        loadimm m0, 5
+        loadimm m1, 0
+        loadimm m2, loop
+    loop:
+        dec m0
+        inc m3
+        compeq m0, m1
+        halt
+        jmp m2
+    ",
+    )
+    .unwrap();
+    let mut cpu = CPU::new(program, Vec::new()).unwrap();
+    assert_eq!(cpu.run(100).0, Halted::Halted);
+    assert_eq!(cpu.read(128), Some(0));
+    assert_eq!(cpu.read(131), Some(5));
+}
+
+#[test]
+fn jump_to_label() {
+    let program = assemble(
+        "
+        loadimm m0, 3
+    loop:
+        dec m0
+        call f
+        compeq m0, m1
+        jmprel loop
+        halt
+    f:
+        inc m1
+        inc m1
+        ret
+    ",
+    )
+    .unwrap();
+    // The targets are read from the last bytes of the image
+    assert_eq!(program[126], 18);
+    assert_eq!(program[125], (3i8 - 15) as u8);
+    assert_eq!(
+        Instruction::from_3bytes([program[6], program[7], program[8]]),
+        Instruction::Call(false, false, false, false, 126)
+    );
+    let mut cpu = CPU::new(program, Vec::new()).unwrap();
+    assert_eq!(cpu.run(100).0, Halted::Halted);
+    // Loops while both are equal
+    assert_eq!(cpu.read(128), Some(1));
+    assert_eq!(cpu.read(129), Some(4));
+
+    // Every jump to the same label shares its byte
+    let program = assemble("loop:\ninc m0\njmp loop\njmp loop").unwrap();
+    assert_eq!(program[3..9], [0x03, 126, 6, 0x03, 126, 6]);
+    assert_eq!(program[126], 0);
+
+    // There is no room left for the target
+    let source = format!("loop:\n{}jmp loop\n.byte 0", "inc m0\n".repeat(41));
+    assert_eq!(
+        assemble(&source),
+        Err(AssembleError::ProgramTooLarge { line: 43 })
+    );
+}
@@ -0,0 +1,181 @@
+use y_cpu::{assembler::assemble, Halted, Instruction, CPU};
+
+#[test]
+fn halt() {
+    let mut program = [0; 127];
+    // NOT m0, HALT, NOT m0
+    program[..9].copy_from_slice(&[0x03, 128, 0, 0x03, 0, 1, 0x03, 128, 0]);
+    let mut cpu = CPU::new(program, Vec::new()).unwrap();
+    assert_eq!(cpu.tick(), Halted::Running);
+    assert_eq!(cpu.tick(), Halted::Halted);
+    assert_eq!(cpu.reg_zero, 3);
+    // Ticking again stays on the halt
+    assert_eq!(cpu.tick(), Halted::Halted);
+    assert_eq!(cpu.reg_zero, 3);
+    assert_eq!(cpu.data_mem[0], 0xFF);
+}
+
+#[test]
+fn comparison_advance() {
+    for (value, pc) in [(0, 3), (1, 6)] {
+        let mut cpu = CPU::new(assemble("compeq m0, m1").unwrap(), Vec::new()).unwrap();
+        cpu.data_mem[0] = value;
+        cpu.tick();
+        assert_eq!(cpu.reg_zero, pc);
+    }
+}
+
+#[test]
+fn run() {
+    let mut cpu = CPU::new(assemble("not m0\nnot m0\nhalt").unwrap(), Vec::new()).unwrap();
+    assert_eq!(cpu.run(100), (Halted::Halted, 3));
+
+    // Sets the PC back by one instruction before the advance, forever
+    let mut cpu = CPU::new(assemble("copy m0, r0").unwrap(), Vec::new()).unwrap();
+    cpu.data_mem[0] = 253;
+    assert_eq!(cpu.run(100), (Halted::Running, 100));
+    assert_eq!(cpu.reg_zero, 0);
+}
+
+#[test]
+fn jumps() {
+    let mut cpu = CPU::new(assemble("loadimm m0, 30\njmp m0").unwrap(), Vec::new()).unwrap();
+    cpu.run(2);
+    assert_eq!(cpu.reg_zero, 30);
+
+    // -6 from the instruction following the one at 6 goes back to 3
+    let program = assemble("loadimm m0, 0xFA\ninc m1\njmprel m0").unwrap();
+    let mut cpu = CPU::new(program, Vec::new()).unwrap();
+    cpu.run(3);
+    assert_eq!(cpu.reg_zero, 3);
+    cpu.run(2);
+    assert_eq!(cpu.data_mem[1], 2);
+}
+
+#[test]
+fn call_ret() {
+    let program = assemble(
+        "
+        loadimm m0, 12
+        call m0
+        inc m2
+        halt
+        inc m1
+        ret
+    ",
+    )
+    .unwrap();
+    let mut cpu = CPU::new(program, Vec::new()).unwrap();
+    assert_eq!(cpu.run(10), (Halted::Halted, 6));
+    assert_eq!(cpu.data_mem[1], 1);
+    // Resumed right after the call
+    assert_eq!(cpu.data_mem[2], 1);
+    assert_eq!(cpu.reg_zero, 9);
+    assert_eq!(cpu.sp, 64);
+}
+
+#[test]
+fn signed_comparison() {
+    // 0xFF is 255 unsigned, but -1 signed
+    for (compgt, greater) in [("compgt", true), ("compgt.s1.s2", false)] {
+        let source = format!("loadimm m0, 0xFF\nloadimm m1, 1\n{compgt} m0, m1\ninc m2\nhalt");
+        let mut cpu = CPU::new(assemble(&source).unwrap(), Vec::new()).unwrap();
+        cpu.run(10);
+        assert_eq!(cpu.read(130), Some(greater as u8), "{compgt}");
+    }
+}
+
+#[test]
+fn tick_n() {
+    let mut cpu = CPU::new([0; 127], Vec::new()).unwrap();
+    assert_eq!(cpu.tick_n(5), (Halted::Running, 5));
+    assert_eq!(cpu.reg_zero, 15);
+
+    // Only 3 more instructions fit before the end of instruction memory
+    cpu.reg_zero = 117;
+    assert_eq!(cpu.tick_n(5), (Halted::Halted, 3));
+}
+
+#[test]
+fn comp_ne() {
+    for (value, pc) in [(0, 9), (1, 6)] {
+        let program = assemble(&format!("loadimm m0, {value}\ncompne m0, m1")).unwrap();
+        let mut cpu = CPU::new(program, Vec::new()).unwrap();
+        cpu.run(2);
+        assert_eq!(cpu.reg_zero, pc);
+    }
+}
+
+#[test]
+fn comp_ge_le_on_equal() {
+    for comparison in ["compge", "comple", "compge.s1.s2", "comple.s1.s2"] {
+        let source = format!("loadimm m0, 0x90\nloadimm m1, 0x90\n{comparison} m0, m1");
+        let mut cpu = CPU::new(assemble(&source).unwrap(), Vec::new()).unwrap();
+        cpu.run(3);
+        // Holds, so the following instruction is not skipped
+        assert_eq!(cpu.reg_zero, 9, "{comparison}");
+    }
+}
+
+#[test]
+fn flat_origin() {
+    let program = assemble("loadimm m0, 5\ninc m0\nhalt").unwrap();
+    let mut cpu = CPU::new_flat(&program[..9], 1000, Vec::new()).unwrap();
+    assert_eq!(cpu.pc(), 1000);
+    assert_eq!(cpu.run(10), (Halted::Halted, 3));
+    assert_eq!(cpu.read(128), Some(6));
+    assert_eq!(cpu.pc(), 1006);
+}
+
+#[test]
+fn skip_count() {
+    let program = assemble(
+        "
+        loadimm m0, 1
+        loadimm m2, 3
+        compeq.skip m0, m1
+        inc m5
+        inc m5
+        inc m5
+        inc m6
+        halt
+    ",
+    )
+    .unwrap();
+    assert_eq!(
+        Instruction::from_3bytes([program[6], program[7], program[8]]).to_string(),
+        "COMPEQ.skip m0, m1"
+    );
+    let mut cpu = CPU::new(program, Vec::new()).unwrap();
+    assert_eq!(cpu.run(10), (Halted::Halted, 5));
+    assert_eq!(cpu.read(133), Some(0));
+    assert_eq!(cpu.read(134), Some(1));
+}
+
+#[test]
+fn skip_past_the_end() {
+    // 90 instructions further is past 255, where the program counter would wrap around
+    let program = assemble("loadimm m0, 1\nloadimm m2, 90\ncompeq.skip m0, m1\nhalt").unwrap();
+    let mut cpu = CPU::new(program, Vec::new()).unwrap();
+    assert_eq!(cpu.run(10), (Halted::Halted, 4));
+    assert_eq!(cpu.pc(), 255);
+}
+
+#[test]
+fn end_of_instruction_memory() {
+    let mut program = [0; 127];
+    // inc m0 in the last slot that fits
+    program[124..].copy_from_slice(&assemble("inc m0").unwrap()[..3]);
+    let mut cpu = CPU::new(program, Vec::new()).unwrap();
+    cpu.set_pc(124);
+    assert_eq!(cpu.tick(), Halted::Running);
+    assert_eq!(cpu.read(128), Some(1));
+    assert_eq!(cpu.pc(), 127);
+    assert_eq!(cpu.tick(), Halted::Halted);
+
+    for pc in [125, 126, 255] {
+        cpu.set_pc(pc);
+        assert_eq!(cpu.tick(), Halted::Halted);
+        assert_eq!(cpu.pc(), pc);
+    }
+}
@@ -0,0 +1,248 @@
+use std::{cell::RefCell, rc::Rc};
+
+use y_cpu::{
+    assembler::assemble, AccessEvent, DebugRecord, Halted, Instruction, StopReason, WatchRecord,
+    CPU,
+};
+
+fn cpu(source: &str) -> CPU {
+    CPU::new(assemble(source).unwrap(), Vec::new()).unwrap()
+}
+
+#[test]
+fn debug_log() {
+    let mut cpu = cpu("loadimm.debug m0, 7\nloadimm m1, 8\nhalt");
+    cpu.run(10);
+    assert_eq!(
+        cpu.debug_log,
+        [DebugRecord {
+            pc: 0,
+            addr: 128,
+            data: 7
+        }]
+    );
+}
+
+#[test]
+fn debug_log_skips_failed_writes() {
+    let mut cpu = cpu("loadimm.debug dev6, 7\nloadimm.debug r5, 8\nloadimm.debug m0, 9\nhalt");
+    cpu.allow_self_modify = false;
+    cpu.watch(5);
+    cpu.run(10);
+    assert_eq!(
+        cpu.debug_log,
+        [DebugRecord {
+            pc: 6,
+            addr: 128,
+            data: 9
+        }]
+    );
+    assert!(cpu.watch_log.is_empty());
+}
+
+#[test]
+fn trace_hook() {
+    let mut cpu = cpu("inc m0\ninc m1\nhalt");
+    let trace = Rc::new(RefCell::new(Vec::new()));
+    let hook_trace = trace.clone();
+    cpu.set_trace_hook(Box::new(move |pc, inst| {
+        hook_trace.borrow_mut().push((pc, inst.to_string()))
+    }));
+    for _ in 0..3 {
+        cpu.tick();
+    }
+    assert_eq!(
+        *trace.borrow(),
+        [
+            (0, "INC m0".to_owned()),
+            (3, "INC m1".to_owned()),
+            (6, "HALT".to_owned()),
+        ]
+    );
+}
+
+#[test]
+fn breakpoint() {
+    let mut cpu = cpu("inc m0\ninc m0\ninc m0\nhalt");
+    cpu.add_breakpoint(6);
+    assert_eq!(cpu.run_to_breakpoint(10), StopReason::Breakpoint(6));
+    assert_eq!(cpu.data_mem[0], 2);
+    // Resuming runs the instruction at the breakpoint
+    assert_eq!(cpu.run_to_breakpoint(10), StopReason::Halted);
+    assert_eq!(cpu.data_mem[0], 3);
+}
+
+#[test]
+fn watchpoint() {
+    let mut cpu = cpu("loadimm m0, 5\nloadimm m1, 6\ninc m0\nhalt");
+    cpu.watch(128);
+    cpu.run(10);
+    assert_eq!(
+        cpu.watch_log,
+        [
+            WatchRecord {
+                pc: 0,
+                addr: 128,
+                old: 0,
+                new: 5
+            },
+            WatchRecord {
+                pc: 6,
+                addr: 128,
+                old: 5,
+                new: 6
+            },
+        ]
+    );
+}
+
+#[test]
+fn step() {
+    let mut cpu = cpu("loadimm m0, 5\ninc m0\nhalt");
+    let (inst, halted) = cpu.step();
+    assert_eq!(
+        (inst.to_string(), halted),
+        ("LOADIMM m0, 5".to_owned(), Halted::Running)
+    );
+    let (inst, halted) = cpu.step();
+    assert_eq!(
+        (inst.to_string(), halted),
+        ("INC m0".to_owned(), Halted::Running)
+    );
+    assert_eq!(cpu.data_mem[0], 6);
+}
+
+#[test]
+fn opcode_counts() {
+    let mut cpu = cpu("
+        loadimm m0, 3
+        loadimm m1, 0
+        loadimm m2, 9
+        dec m0
+        compeq m0, m1
+        halt
+        jmp m2
+    ");
+    assert_eq!(cpu.run(100).0, Halted::Halted);
+    let mut expected = [0; 16];
+    // loadimm
+    expected[12] = 3;
+    // dec, jmp and halt
+    expected[3] = 3 + 2 + 1;
+    // compeq
+    expected[13] = 3;
+    assert_eq!(cpu.opcode_counts(), expected);
+
+    cpu.reset_counts();
+    assert_eq!(cpu.opcode_counts(), [0; 16]);
+}
+
+#[test]
+fn steps() {
+    let mut cpu = cpu("loadimm m0, 1\ninc m0\ninc m0\nhalt\ninc m0");
+    let steps: Vec<(u8, Halted)> = cpu.steps().map(|(pc, _, halted)| (pc, halted)).collect();
+    assert_eq!(
+        steps,
+        [
+            (0, Halted::Running),
+            (3, Halted::Running),
+            (6, Halted::Running),
+            (9, Halted::Halted),
+        ]
+    );
+    assert_eq!(cpu.read(128), Some(3));
+}
+
+#[test]
+fn cycles() {
+    let mut cpu = cpu("inc m0\ninc m0\nhalt");
+    assert_eq!(cpu.cycles(), 0);
+    let (halted, steps) = cpu.run(100);
+    assert_eq!(halted, Halted::Halted);
+    // Every instruction takes one cycle by default
+    assert_eq!(cpu.cycles(), steps as u64);
+    cpu.reset();
+    assert_eq!(cpu.cycles(), 0);
+}
+
+#[test]
+fn cost_model() {
+    let mut cpu = cpu("mul m0, m1\ninc m0\nmul m0, m1\nhalt");
+    cpu.set_cost_model(|inst| match inst {
+        Instruction::Mul(..) => 4,
+        _ => 1,
+    });
+    assert_eq!(cpu.run(10), (Halted::Halted, 4));
+    assert_eq!(cpu.cycles(), 4 + 1 + 4 + 1);
+}
+
+#[test]
+fn debug_output() {
+    let mut cpu = cpu("inc m0\nloadimm m1, 9\nhalt");
+    cpu.tick();
+    let debug = format!("{cpu:?}");
+    assert!(debug.contains("reg_zero: 3"), "{debug}");
+    assert!(debug.contains("next: LOADIMM m1, 9"), "{debug}");
+}
+
+#[test]
+fn access_log() {
+    let mut cpu = cpu("loadimm m0, 5\ncopy m0, m1\nadd m0, m1\nhalt");
+    cpu.log_accesses = true;
+    cpu.run(3);
+    use AccessEvent::{Load, Store};
+    assert_eq!(
+        cpu.access_log,
+        [
+            Store {
+                pc: 0,
+                addr: 128,
+                value: 5
+            },
+            Load {
+                pc: 3,
+                addr: 128,
+                value: 5
+            },
+            Store {
+                pc: 3,
+                addr: 129,
+                value: 5
+            },
+            Load {
+                pc: 6,
+                addr: 128,
+                value: 5
+            },
+            Load {
+                pc: 6,
+                addr: 129,
+                value: 5
+            },
+            Store {
+                pc: 6,
+                addr: 128,
+                value: 10
+            },
+        ]
+    );
+}
+
+#[test]
+fn preview() {
+    let mut cpu = cpu("loadimm m0, 2\nloadimm m1, 3\nadd m0, m1\nhalt");
+    cpu.run(2);
+    let effect = cpu.preview();
+    assert_eq!(
+        effect.instruction,
+        Instruction::Add(false, false, false, false, 128, 129)
+    );
+    assert_eq!(effect.reads, [(128, 2), (129, 3)]);
+    assert_eq!(effect.writes, [(128, 5)]);
+    assert_eq!(effect.next_pc, 9);
+    assert_eq!(effect.halted, Halted::Running);
+
+    assert_eq!(cpu.pc(), 6);
+    assert_eq!(cpu.read(128), Some(2));
+    assert!(cpu.access_log.is_empty());
+}
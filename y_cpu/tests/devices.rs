@@ -0,0 +1,412 @@
+use std::{cell::RefCell, ops::RangeInclusive, rc::Rc};
+
+use y_cpu::{
+    assembler::assemble,
+    devices::{ConsoleDevice, RngDevice},
+    CpuBuilder, CpuError, CpuInitError, Device, ErrorKind, Halted, CPU, DEVICE_BASE,
+};
+
+/// Records every write it gets in a log shared with the test, and reads back
+/// the last value written
+#[derive(Clone, Default)]
+struct Recorder {
+    address: u8,
+    writes: Rc<RefCell<Vec<(u8, u8)>>>,
+}
+
+impl Recorder {
+    fn new(address: u8) -> Recorder {
+        Recorder {
+            address,
+            ..Recorder::default()
+        }
+    }
+}
+
+impl Device for Recorder {
+    fn load(&mut self, _addr: u8) -> u8 {
+        self.writes.borrow().last().map_or(0, |&(_, data)| data)
+    }
+
+    fn push(&mut self, addr: u8, data: u8) {
+        self.writes.borrow_mut().push((addr, data));
+    }
+
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn clone_box(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
+
+/// Counts the ticks it gets, which can also be written directly
+#[derive(Clone, Default)]
+struct Counter {
+    address: u8,
+    count: u8,
+    /// Count at which an interrupt is requested, and the vector it requests
+    interrupt: Option<(u8, u8)>,
+    divider: u32,
+}
+
+impl Device for Counter {
+    fn load(&mut self, _addr: u8) -> u8 {
+        self.count
+    }
+
+    fn push(&mut self, _addr: u8, data: u8) {
+        self.count = data;
+    }
+
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn clone_box(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+
+    fn reset(&mut self) {
+        self.count = 0;
+    }
+
+    fn tick(&mut self) {
+        self.count = self.count.wrapping_add(1);
+    }
+
+    fn clock_divider(&self) -> u32 {
+        self.divider
+    }
+
+    fn interrupt_pending(&self) -> Option<u8> {
+        let (count, vector) = self.interrupt?;
+        (self.count == count).then_some(vector)
+    }
+}
+
+/// Requests an interrupt for as long as its line is up, which writing 0 lowers
+#[derive(Clone)]
+struct Line {
+    raised: bool,
+    vector: u8,
+}
+
+impl Device for Line {
+    fn load(&mut self, _addr: u8) -> u8 {
+        self.raised as u8
+    }
+
+    fn push(&mut self, _addr: u8, data: u8) {
+        self.raised = data != 0;
+    }
+
+    fn address(&self) -> u8 {
+        200
+    }
+
+    fn clone_box(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+
+    fn interrupt_pending(&self) -> Option<u8> {
+        self.raised.then_some(self.vector)
+    }
+}
+
+/// Four registers mapped from 200 to 203
+#[derive(Clone, Default)]
+struct Registers([u8; 4]);
+
+impl Device for Registers {
+    fn load(&mut self, addr: u8) -> u8 {
+        self.0[(addr - 200) as usize]
+    }
+
+    fn push(&mut self, addr: u8, data: u8) {
+        self.0[(addr - 200) as usize] = data;
+    }
+
+    fn address(&self) -> u8 {
+        200
+    }
+
+    fn clone_box(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+
+    fn address_range(&self) -> RangeInclusive<u8> {
+        200..=203
+    }
+}
+
+/// Runs `source` with `devices` plugged in until it halts
+fn run(source: &str, devices: Vec<Box<dyn Device>>) -> CPU {
+    let mut cpu = CPU::new(assemble(source).unwrap(), devices).unwrap();
+    assert_eq!(cpu.run(100).0, Halted::Halted);
+    cpu
+}
+
+#[test]
+fn device_addresses() {
+    let devices: Vec<Box<dyn Device>> =
+        vec![Box::new(Recorder::new(194)), Box::new(Recorder::new(255))];
+    assert!(CPU::new([0; 127], devices).is_ok());
+
+    let devices: Vec<Box<dyn Device>> = vec![Box::new(Recorder::new(100))];
+    assert_eq!(
+        CPU::new([0; 127], devices).err(),
+        Some(CpuInitError::AddressOutOfRange(100))
+    );
+}
+
+#[test]
+fn copy_to_device() {
+    let recorder = Recorder::new(200);
+    let mut program = [0; 127];
+    // COPY m0, dev6
+    program[..3].copy_from_slice(&[0x0C, 128, 200]);
+    let mut cpu = CPU::new(program, vec![Box::new(recorder.clone())]).unwrap();
+    cpu.data_mem[0] = 42;
+    cpu.tick();
+    assert_eq!(*recorder.writes.borrow(), [(200, 42)]);
+}
+
+#[test]
+fn device_reset() {
+    let counter = Counter {
+        address: 200,
+        ..Counter::default()
+    };
+    let mut cpu = run(
+        "loadimm m0, 42\ncopy m0, dev6\nhalt",
+        vec![Box::new(counter)],
+    );
+    // Ticked once more after the copy, and once after the halt
+    assert_eq!(cpu.try_load(200), Ok(44));
+    cpu.reset();
+    assert_eq!(cpu.try_load(200), Ok(0));
+}
+
+#[test]
+fn device_ticks() {
+    let counter = Counter {
+        address: 200,
+        ..Counter::default()
+    };
+    let mut cpu = CPU::new([0; 127], vec![Box::new(counter)]).unwrap();
+    for ticks in 1..=5 {
+        assert_eq!(cpu.tick(), Halted::Running);
+        assert_eq!(cpu.try_load(200), Ok(ticks));
+    }
+}
+
+#[test]
+fn interrupt() {
+    let counter = Counter {
+        address: 200,
+        interrupt: Some((3, 30)),
+        ..Counter::default()
+    };
+    let mut cpu = CPU::new([0; 127], vec![Box::new(counter)]).unwrap();
+    cpu.run(2);
+    assert_eq!(cpu.reg_zero, 6);
+    cpu.tick();
+    assert_eq!(cpu.reg_zero, 30);
+    // The address of the instruction that would have run next is on the stack
+    assert_eq!(cpu.sp, 63);
+    assert_eq!(cpu.data_mem[63], 9);
+}
+
+#[test]
+fn interrupts_disabled() {
+    let counter = Counter {
+        address: 200,
+        interrupt: Some((3, 30)),
+        ..Counter::default()
+    };
+    let mut cpu = CPU::new([0; 127], vec![Box::new(counter)]).unwrap();
+    cpu.interrupts_enabled = false;
+    cpu.run(3);
+    // The request is left pending and execution carries on in order
+    assert_eq!(cpu.read(200), Some(3));
+    assert_eq!(cpu.reg_zero, 9);
+    assert_eq!(cpu.sp, 64);
+}
+
+#[test]
+fn level_triggered_interrupt() {
+    let program = assemble(
+        "
+        inc m0
+        inc m0
+        halt
+        noop
+        inc m1
+        inc m1
+        copy m2, dev6
+        ret
+    ",
+    )
+    .unwrap();
+    let line = Line {
+        raised: true,
+        vector: 12,
+    };
+    let mut cpu = CPU::new(program, vec![Box::new(line)]).unwrap();
+    cpu.tick();
+    assert_eq!(cpu.reg_zero, 12);
+    assert!(cpu.in_interrupt());
+    // The line stays up until the handler lowers it, without entering the handler again
+    assert_eq!(cpu.run(10), (Halted::Halted, 6));
+    assert!(!cpu.in_interrupt());
+    assert_eq!(cpu.data_mem[0], 2);
+    assert_eq!(cpu.data_mem[1], 2);
+    assert_eq!(cpu.sp, 64);
+}
+
+#[test]
+fn acknowledge_interrupt() {
+    let program = assemble(
+        "noop
+noop
+noop
+noop
+inc m0
+inc m0
+inc m0",
+    )
+    .unwrap();
+    let line = Line {
+        raised: true,
+        vector: 12,
+    };
+    let mut cpu = CPU::new(program, vec![Box::new(line)]).unwrap();
+    cpu.tick();
+    cpu.tick();
+    assert_eq!((cpu.reg_zero, cpu.sp), (15, 63));
+
+    // The line is still up, so the handler is entered again right away
+    cpu.acknowledge_interrupt();
+    cpu.tick();
+    assert_eq!((cpu.reg_zero, cpu.sp), (12, 62));
+    assert_eq!(cpu.data_mem[62], 18);
+}
+
+#[test]
+fn address_range() {
+    let mut cpu = run(
+        "loadimm m0, 1\nloadimm m1, 2\ncopy m0, dev6\ncopy m1, dev9\nhalt",
+        vec![Box::new(Registers::default())],
+    );
+    assert_eq!(cpu.try_load(200), Ok(1));
+    assert_eq!(cpu.try_load(201), Ok(0));
+    cpu.try_push(202, 3).unwrap();
+    assert_eq!(cpu.try_load(202), Ok(3));
+    assert_eq!(cpu.try_load(203), Ok(2));
+    assert_eq!(cpu.try_load(204), Err(CpuError::UnmappedDevice(204)));
+
+    // The range overlaps another device
+    let devices: Vec<Box<dyn Device>> =
+        vec![Box::new(Registers::default()), Box::new(Recorder::new(203))];
+    assert_eq!(
+        CPU::new([0; 127], devices).err(),
+        Some(CpuInitError::DuplicateAddress(203))
+    );
+}
+
+#[test]
+fn console() {
+    let console = ConsoleDevice::new(200);
+    let output = console.output_handle();
+    let cpu = run(
+        "loadimm m0, 72\nloadimm m1, 105\ncopy m0, dev6\ncopy m1, dev6\nhalt",
+        vec![Box::new(console)],
+    );
+    assert_eq!(*output.bytes(), *b"Hi");
+
+    // Copies of the CPU write to their own console
+    let mut copy = cpu.clone();
+    copy.write(200, b'!').unwrap();
+    assert_eq!(*output.bytes(), *b"Hi");
+}
+
+#[test]
+fn rng() {
+    let mut cpu = CPU::new([0; 127], vec![Box::new(RngDevice::new(200, 1))]).unwrap();
+    let sequence: Vec<u8> = (0..5).map(|_| cpu.try_load(200).unwrap()).collect();
+    assert_eq!(sequence, [0, 4, 157, 18, 142]);
+
+    // Reseeding starts the same sequence over
+    cpu.try_push(200, 1).unwrap();
+    assert_eq!(cpu.try_load(200), Ok(0));
+    assert_eq!(cpu.try_load(200), Ok(4));
+}
+
+#[test]
+fn builder() {
+    let recorder = Recorder::new(200);
+    let program = assemble("copy m0, dev6\nhalt").unwrap();
+    let mut data = [0; 64];
+    data[0] = b'!';
+    let mut cpu = CpuBuilder::new()
+        .instructions(&program[..6])
+        .initial_data(&data)
+        .device(Box::new(recorder.clone()))
+        .build()
+        .unwrap();
+    assert_eq!(cpu.run(10), (Halted::Halted, 2));
+    assert_eq!(*recorder.writes.borrow(), [(200, b'!')]);
+}
+
+#[test]
+fn below_device_base() {
+    let devices: Vec<Box<dyn Device>> = vec![Box::new(Recorder::new(DEVICE_BASE - 1))];
+    assert_eq!(
+        CPU::new([0; 127], devices).err(),
+        Some(CpuInitError::AddressOutOfRange(193))
+    );
+}
+
+#[test]
+fn clock_divider() {
+    let counter = Counter {
+        address: 200,
+        divider: 3,
+        ..Counter::default()
+    };
+    let mut cpu = CPU::new([0; 127], vec![Box::new(counter)]).unwrap();
+    let mut ticked_on = Vec::new();
+    for cycle in 1..=10 {
+        let before = cpu.read(200);
+        cpu.tick();
+        if cpu.read(200) != before {
+            ticked_on.push(cycle);
+        }
+    }
+    assert_eq!(ticked_on, [3, 6, 9]);
+}
+
+#[test]
+fn device_at() {
+    let cpu = CPU::new([0; 127], vec![Box::new(ConsoleDevice::new(200))]).unwrap();
+    assert_eq!(cpu.device_at(200).map(|device| device.address()), Some(200));
+    assert!(cpu.device_at(201).is_none());
+    assert!(cpu.device_at(128).is_none());
+}
+
+#[test]
+fn hot_plug() {
+    let program = assemble("loadimm.halt dev6, 1\nloadimm.halt dev6, 2\nhalt").unwrap();
+    let mut cpu = CPU::new(program, Vec::new()).unwrap();
+    let recorder = Recorder::new(200);
+    cpu.add_device(Box::new(recorder.clone())).unwrap();
+    assert_eq!(cpu.tick(), Halted::Running);
+    assert_eq!(*recorder.writes.borrow(), [(200, 1)]);
+
+    assert!(cpu.remove_device(200).is_some());
+    assert_eq!(cpu.tick(), Halted::Errored(ErrorKind::UnmappedDevice(200)));
+    assert_eq!(*recorder.writes.borrow(), [(200, 1)]);
+    assert!(cpu.remove_device(200).is_none());
+}
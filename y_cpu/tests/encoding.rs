@@ -0,0 +1,117 @@
+use y_cpu::{DecodeError, InstFlags, Instruction, Opcode, CPU};
+
+#[test]
+fn size() {
+    for first in 0..=255 {
+        for selector in 0..=20 {
+            let inst = Instruction::from_3bytes([first, 128, selector]);
+            assert_eq!(inst.size(), 3, "{inst}");
+            assert!(inst.size() <= Instruction::MAX_SIZE);
+        }
+    }
+}
+
+#[test]
+fn round_trip() {
+    // Every opcode with every combination of the halt, debug and signing bits, along with
+    // every selector of the single argument operations
+    for first in 0..=255 {
+        for arg2 in [0, 1, 9, 17, 18, 0x81, 255] {
+            let inst = Instruction::from_3bytes([first, 0x42, arg2]);
+            let bytes = inst.to_3bytes();
+            assert_eq!(bytes[0] & 0b1111, first & 0b1111, "{inst}");
+            assert_eq!(Instruction::from_3bytes(bytes), inst);
+        }
+    }
+}
+
+#[test]
+fn display() {
+    let cases = [
+        (
+            Instruction::Add(false, false, false, false, 128, 129),
+            "ADD m0, m1",
+        ),
+        (
+            Instruction::Sub(true, true, true, false, 5, 191),
+            "SUB.s1.halt.debug r5, m63",
+        ),
+        (
+            Instruction::Copy(false, false, false, false, 192, 193),
+            "COPY ip, dp",
+        ),
+        (
+            Instruction::Not(false, false, false, false, 195),
+            "NOT flags",
+        ),
+        (
+            Instruction::Inc(false, false, false, false, 196),
+            "INC dev2",
+        ),
+        (Instruction::Halt(false, false, false, false), "HALT"),
+    ];
+    for (inst, text) in cases {
+        assert_eq!(inst.to_string(), text);
+    }
+}
+
+#[test]
+fn decode_checked() {
+    let cpu = CPU::new([0; 127], Vec::new()).unwrap();
+    let add = Instruction::Add(false, false, false, false, 128, 129);
+    assert_eq!(cpu.decode_checked(add.to_3bytes()), Ok(add));
+
+    let copy = Instruction::Copy(false, false, false, false, 128, 200);
+    assert_eq!(
+        cpu.decode_checked(copy.to_3bytes()).err(),
+        Some(DecodeError::BadAddress(200))
+    );
+}
+
+#[test]
+fn nibble_round_trip() {
+    for nibble in 0..16 {
+        let opcode = Opcode::from_nibble(nibble).unwrap();
+        assert_eq!(opcode.to_nibble(), nibble);
+    }
+    assert_eq!(Opcode::from_nibble(16), None);
+    assert_eq!(
+        Instruction::Mod(false, false, false, false, 128, 129).opcode(),
+        Opcode::Mod
+    );
+}
+
+#[test]
+fn operands_and_flags() {
+    let inc = Instruction::Inc(true, false, true, false, 130);
+    assert_eq!(inc.operands(), (Some(130), None));
+    assert_eq!(
+        inc.flags(),
+        InstFlags {
+            halt_on_error: true,
+            store_debug_info: false,
+            arg1_signed: true,
+            arg2_signed: false,
+        }
+    );
+
+    let sub = Instruction::Sub(false, true, false, true, 128, 5);
+    assert_eq!(sub.operands(), (Some(128), Some(5)));
+    assert!(sub.flags().store_debug_info && sub.flags().arg2_signed);
+
+    assert_eq!(
+        Instruction::Ret(false, false, false, false).operands(),
+        (None, None)
+    );
+}
+
+#[test]
+fn equality() {
+    let add = Instruction::from_3bytes([0x04, 128, 129]);
+    assert_eq!(add, Instruction::from_3bytes([0x04, 128, 129]));
+    assert_eq!(add, Instruction::Add(false, false, false, false, 128, 129));
+    assert_ne!(add, Instruction::from_3bytes([0x04, 128, 130]));
+    // Same arguments, but the halt bit is set
+    assert_ne!(add, Instruction::from_3bytes([0x84, 128, 129]));
+    assert_ne!(add, Instruction::from_3bytes([0x05, 128, 129]));
+}
@@ -0,0 +1,173 @@
+use y_cpu::{assembler::assemble, execute_triple, Flags, Halted, CPU};
+
+/// Runs `source` until it halts, with `data` at the start of data memory
+fn run_on(source: &str, data: &[u8]) -> CPU {
+    let mut cpu = CPU::new(assemble(source).unwrap(), Vec::new()).unwrap();
+    cpu.data_mem[0..data.len()].copy_from_slice(data);
+    for _ in 0..100 {
+        if cpu.tick() != Halted::Running {
+            return cpu;
+        }
+    }
+    panic!("the program did not halt");
+}
+
+#[test]
+fn add_flags() {
+    let cpu = run_on("add m0, m1\nhalt", &[255, 1]);
+    assert_eq!(cpu.flags.bits(), Flags::CARRY.bits() | Flags::ZERO.bits());
+
+    let cpu = run_on("add m0, m1\nhalt", &[127, 1]);
+    assert_eq!(
+        cpu.flags.bits(),
+        Flags::OVERFLOW.bits() | Flags::NEGATIVE.bits()
+    );
+}
+
+#[test]
+fn xor() {
+    let cpu = run_on("xor m0, m1\nhalt", &[0xFF, 0x0F]);
+    assert_eq!(cpu.data_mem[0], 0xF0);
+    assert_eq!(cpu.flags, Flags::NEGATIVE);
+
+    let cpu = run_on("xor m0, m1\nhalt", &[0x5A, 0x5A]);
+    assert_eq!(cpu.data_mem[0], 0);
+    assert_eq!(cpu.flags, Flags::ZERO);
+}
+
+#[test]
+fn load_imm() {
+    let cpu = run_on("loadimm m0, 42\nhalt", &[]);
+    assert_eq!(cpu.data_mem[0], 42);
+    // The second argument is never read as an address
+    assert_eq!(cpu.inst_mem[42], 0);
+}
+
+#[test]
+fn sra() {
+    let cpu = run_on(
+        "loadimm m0, 0x80\nloadimm m1, 0x80\nloadimm m2, 1\nsra m0\nsr m1, m2\nhalt",
+        &[],
+    );
+    // SRA keeps the sign bit, SR shifts a zero in
+    assert_eq!(cpu.data_mem[0], 0xC0);
+    assert_eq!(cpu.data_mem[1], 0x40);
+}
+
+#[test]
+fn shift_by_count() {
+    let cpu = run_on(
+        "loadimm m0, 5\nloadimm m1, 3\nsl m0, m1\nloadimm m2, 0x12\nloadimm m3, 4\nrr m2, m3\nhalt",
+        &[],
+    );
+    assert_eq!(cpu.data_mem[0], 40);
+    assert_eq!(cpu.data_mem[2], 0x21);
+}
+
+#[test]
+fn bit_test() {
+    // m0 is 0b0101
+    for (mask, zero) in [
+        (0b0000_0100, false),
+        (0b0000_1000, true),
+        (0b1111_1111, false),
+    ] {
+        let mut cpu = run_on(
+            &format!("loadimm m0, 5\nloadimm m1, {mask}\nbittest m0, m1\nhalt"),
+            &[],
+        );
+        assert_eq!(cpu.zero(), zero, "mask {mask:#b}");
+        // Neither argument is written
+        assert_eq!(cpu.read(128), Some(0b0101));
+        assert_eq!(cpu.read(129), Some(mask));
+    }
+}
+
+#[test]
+fn nand_nor() {
+    let mut cpu = run_on("loadimm m0, 0xF0\nloadimm m1, 0x3C\nnand m0, m1\nhalt", &[]);
+    assert_eq!(cpu.read(128), Some(0xCF));
+
+    let mut cpu = run_on("loadimm m0, 0xF0\nloadimm m1, 0x0C\nnor m0, m1\nhalt", &[]);
+    assert_eq!(cpu.read(128), Some(0x03));
+
+    let mut cpu = run_on("loadimm m0, 0xF0\nloadimm m1, 0x0F\nnor m0, m1\nhalt", &[]);
+    assert_eq!(cpu.read(128), Some(0));
+    assert!(cpu.zero());
+}
+
+#[test]
+fn popcount_clz() {
+    let mut cpu = run_on(
+        "loadimm m0, 0xB4\npopcount m0\nloadimm m1, 0x10\nclz m1\nclz m2\nhalt",
+        &[],
+    );
+    assert_eq!(cpu.read(128), Some(4));
+    assert_eq!(cpu.read(129), Some(3));
+    // Every bit of zero is a leading zero
+    assert_eq!(cpu.read(130), Some(8));
+}
+
+#[test]
+fn block_copy() {
+    let mut cpu = run_on(
+        "
+        loadimm m0, 1
+        loadimm m1, 2
+        loadimm m2, 3
+        loadimm m3, 4
+        loadimm m4, 5
+        loadimm m10, 148
+        loadimm m11, 4
+        blockcopy m0, m10
+        halt
+    ",
+        &[],
+    );
+    let copied: Vec<_> = (148..153).map(|addr| cpu.read(addr).unwrap()).collect();
+    // Only the 4 bytes of the block
+    assert_eq!(copied, [1, 2, 3, 4, 0]);
+}
+
+#[test]
+fn conditional_moves() {
+    for (setup, zero) in [("add m3, m0", false), ("sub m3, m3", true)] {
+        let cpu = run_on(
+            &format!("loadimm m0, 7\n{setup}\ncmovz m0, m1\ncmovnz m0, m2\nhalt"),
+            &[],
+        );
+        assert_eq!(cpu.data_mem[1], if zero { 7 } else { 0 });
+        assert_eq!(cpu.data_mem[2], if zero { 0 } else { 7 });
+    }
+}
+
+#[test]
+fn random_arguments() {
+    // xorshift, so that the arguments are the same on every run
+    let mut state = 0x2545_f491u32;
+    let mut random = || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as u8
+    };
+    for first in 0..=255 {
+        for _ in 0..200 {
+            let bytes = [first, random(), random()];
+            // Must not panic, whatever it returns
+            execute_triple(bytes);
+        }
+    }
+}
+
+#[test]
+fn swap() {
+    let mut cpu = run_on("loadimm m0, 1\nloadimm m1, 2\nswap m0, m1\nhalt", &[]);
+    assert_eq!(cpu.read(128), Some(2));
+    assert_eq!(cpu.read(129), Some(1));
+
+    // r2 is the literal of the first loadimm
+    let mut cpu = run_on("loadimm m0, 9\nloadimm m1, 3\nswap r2, m1\nhalt", &[]);
+    assert_eq!(cpu.read(2), Some(3));
+    assert_eq!(cpu.read(129), Some(9));
+}
@@ -0,0 +1,25 @@
+use y_cpu::loader::{load_binary, load_ihex, IhexError, LoadError};
+
+#[test]
+fn ihex() {
+    let image = load_ihex(":030000001C80055C\n:02001000DEAD63\n:00000001FF\n").unwrap();
+    assert_eq!(image[..3], [0x1C, 0x80, 0x05]);
+    assert_eq!(image[0x10..0x12], [0xDE, 0xAD]);
+    assert!(image[3..0x10].iter().all(|&byte| byte == 0));
+
+    assert_eq!(
+        load_ihex(":030000001C80055C\n:02001000DEAD64\n:00000001FF\n"),
+        Err(IhexError::BadChecksum { line: 2 })
+    );
+}
+
+#[test]
+fn binary() {
+    assert_eq!(load_binary(&[7; 127]), Ok([7; 127]));
+
+    let image = load_binary(&[1, 2, 3]).unwrap();
+    assert_eq!(image[..3], [1, 2, 3]);
+    assert_eq!(image[3..], [0; 124]);
+
+    assert_eq!(load_binary(&[0; 200]), Err(LoadError::TooLarge(200)));
+}
@@ -0,0 +1,37 @@
+#![cfg(feature = "logging")]
+
+use std::sync::Mutex;
+
+use log::{LevelFilter, Log, Metadata, Record};
+use y_cpu::{assembler::assemble, CPU};
+
+/// Keeps every log line in memory
+struct TestLogger(Mutex<Vec<String>>);
+
+impl Log for TestLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let line = format!("{} {}", record.level(), record.args());
+        self.0.lock().unwrap().push(line);
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: TestLogger = TestLogger(Mutex::new(Vec::new()));
+
+#[test]
+fn trace_per_tick() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(LevelFilter::Trace);
+
+    let mut cpu = CPU::new(assemble("loadimm m0, 42").unwrap(), Vec::new()).unwrap();
+    cpu.tick();
+    assert_eq!(
+        *LOGGER.0.lock().unwrap(),
+        ["TRACE 0x00: LOADIMM m0, 42 -> Running"]
+    );
+}
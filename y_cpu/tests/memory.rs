@@ -0,0 +1,277 @@
+use std::cell::Cell;
+
+use y_cpu::{
+    assembler::assemble, devices::ConsoleDevice, execute_triple, Banker, CpuError, CpuInitError,
+    ErrorKind, Flags, Halted, Instruction, NoSuchBank, CPU, DEVICE_BASE, FLAGS_ADDR,
+};
+
+fn cpu(source: &str) -> CPU {
+    CPU::new(assemble(source).unwrap(), Vec::new()).unwrap()
+}
+
+#[test]
+fn flags_are_mapped_between_devices() {
+    let mut cpu = cpu("loadimm m0, 255\ninc m0\ncopy flags, m1\nhalt");
+    cpu.run(10);
+    assert_eq!(cpu.data_mem[1], Flags::ZERO.bits() | Flags::CARRY.bits());
+
+    let mut cpu = CPU::new([0; 127], Vec::new()).unwrap();
+    assert_eq!(
+        cpu.add_device(Box::new(ConsoleDevice::new(FLAGS_ADDR))),
+        Err(CpuInitError::AddressOutOfRange(FLAGS_ADDR))
+    );
+    assert!(cpu
+        .add_device(Box::new(ConsoleDevice::new(DEVICE_BASE)))
+        .is_ok());
+    assert!(cpu.add_device(Box::new(ConsoleDevice::new(255))).is_ok());
+}
+
+#[test]
+fn with_first_bank() {
+    let banker: Banker<[u8; 127]> = Banker::with_first_bank_and_fill([7; 127], || [0; 127]);
+    assert_eq!(banker.content[0], [7; 127]);
+    assert_eq!(banker.content[1], [0; 127]);
+    assert_eq!(banker.pointer, 0);
+
+    let banker: Banker<Vec<u8>, 3> = Banker::with_first_bank(vec![1, 2]);
+    assert_eq!(banker.content, [vec![1, 2], vec![], vec![]]);
+
+    let banker: Banker<[u8; 4], 0> = Banker::with_first_bank([7; 4]);
+    assert!(banker.content.is_empty());
+}
+
+#[test]
+fn with_first_bank_builds_each_bank_once() {
+    thread_local! {
+        static BUILT: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// A large bank which counts how many times it gets built
+    struct Counted(Box<[u8; 4096]>);
+
+    impl Default for Counted {
+        fn default() -> Counted {
+            BUILT.set(BUILT.get() + 1);
+            Counted(Box::new([0; 4096]))
+        }
+    }
+
+    for _ in 0..100 {
+        let banker: Banker<Counted> = Banker::with_first_bank(Counted(Box::new([7; 4096])));
+        assert_eq!(banker.content[0].0[0], 7);
+        assert_eq!(banker.content[255].0[0], 0);
+    }
+    assert_eq!(BUILT.get(), 100 * 255);
+}
+
+#[test]
+fn bank_switching() {
+    let mut banker: Banker<[u8; 4], 4> = Banker::new([0; 4]);
+    banker[1] = 10;
+    banker.pointer = 3;
+    assert_eq!(banker[1], 0);
+    banker[1] = 30;
+    banker.pointer = 0;
+    assert_eq!(banker[1], 10);
+    assert_eq!(banker.content[3], [0, 30, 0, 0]);
+}
+
+#[test]
+fn try_load() {
+    let mut cpu = CPU::new([0; 127], Vec::new()).unwrap();
+    assert_eq!(cpu.try_load(250), Err(CpuError::UnmappedDevice(250)));
+    assert_eq!(cpu.try_load(128), Ok(0));
+}
+
+#[test]
+fn switch_to() {
+    let mut banker: Banker<[u8; 64]> = Banker::new([0; 64]);
+    banker.switch_to(5).unwrap();
+    banker.current_bank_mut()[3] = 42;
+    banker.switch_to(0).unwrap();
+    assert_eq!(banker.current_bank()[3], 0);
+    banker.switch_to(5).unwrap();
+    assert_eq!(banker.pointer, 5);
+    assert_eq!(banker.current_bank()[3], 42);
+
+    let mut banker: Banker<[u8; 4], 4> = Banker::new([0; 4]);
+    banker.switch_to(3).unwrap();
+    assert_eq!(banker.switch_to(4), Err(NoSuchBank(4)));
+    assert_eq!(banker.pointer, 3);
+}
+
+#[test]
+fn dump_data() {
+    let mut cpu = CPU::new([0; 127], Vec::new()).unwrap();
+    let data = cpu.data_mem.current_bank_mut();
+    data[..12].copy_from_slice(b"Hello, yCPU!");
+    data[13..16].copy_from_slice(&[1, 2, 0xFF]);
+    let dump = cpu.dump_data();
+    assert_eq!(
+        dump.lines().next(),
+        Some("00000000  48 65 6c 6c 6f 2c 20 79  43 50 55 21 00 01 02 ff  |Hello, yCPU!....|")
+    );
+    assert_eq!(dump.lines().count(), 4);
+
+    // Pointed past the last bank from the host
+    cpu.data_mem.pointer = 256;
+    assert_eq!(cpu.dump_data(), "");
+    assert!(format!("{cpu:?}").contains("data: []"));
+}
+
+#[test]
+fn reset() {
+    let mut cpu = cpu("loadimm m0, 5\nloadimm m63, 6\nhalt");
+    cpu.run(10);
+    assert_eq!(cpu.reg_zero, 6);
+    cpu.reset();
+    assert_eq!(cpu.reg_zero, 0);
+    assert_eq!(cpu.data_mem[0], 0);
+    assert_eq!(cpu.data_mem[63], 0);
+    // The program is still there to be run again
+    cpu.run(10);
+    assert_eq!(cpu.data_mem[0], 5);
+}
+
+#[test]
+fn read_write() {
+    let mut cpu = cpu("inc m0\nhalt");
+    cpu.run(10);
+    assert_eq!(cpu.read(0), Some(3));
+    assert_eq!(cpu.read(128), Some(1));
+    assert_eq!(cpu.read(200), None);
+
+    cpu.write(130, 9).unwrap();
+    assert_eq!(cpu.read(130), Some(9));
+    assert_eq!(cpu.write(200, 9), Err(CpuError::UnmappedDevice(200)));
+}
+
+#[test]
+fn bank_data() {
+    let mut cpu = cpu("loadimm m0, 3\nbankdata m0\ncopy m1, m2\nhalt");
+    cpu.data_mem.content[3][1] = 77;
+    cpu.run(10);
+    assert_eq!(cpu.read(193), Some(3));
+    assert_eq!(cpu.read(130), Some(77));
+    assert_eq!(cpu.data_mem.content[0][2], 0);
+}
+
+#[test]
+fn instruction_banks() {
+    let mut cpu = cpu("loadimm m0, 1\nbankinst m0\ninc m2\nhalt");
+    // Only bank 0 gets the program, execution carries on at 6 in bank 1
+    cpu.inst_mem.content[1] = assemble("noop\nnoop\ninc m1\nhalt").unwrap();
+    assert_eq!(cpu.run(10), (Halted::Halted, 4));
+    assert_eq!(cpu.inst_mem.pointer, 1);
+    assert_eq!(cpu.read(129), Some(1));
+    assert_eq!(cpu.read(130), Some(0));
+}
+
+#[test]
+fn set_data_bank() {
+    let mut ramp = [0; 64];
+    for (i, cell) in ramp.iter_mut().enumerate() {
+        *cell = i as u8;
+    }
+    let mut cpu = cpu("copy m10, m63\nhalt");
+    cpu.set_data_bank(0, ramp);
+    cpu.run(10);
+    assert_eq!(cpu.data_mem[10], 10);
+    assert_eq!(cpu.data_mem[63], 10);
+}
+
+#[test]
+fn decode_cache_self_modify() {
+    // Runs the inc at 6 twice, moving its argument from m5 to m6 in between
+    let mut cpu = cpu("
+        loadimm m1, 2
+        loadimm m2, 6
+        inc m5
+        inc r7
+        dec m1
+        compeq m1, m0
+        halt
+        jmp m2
+    ");
+    cpu.set_decode_cache(true);
+    assert_eq!(cpu.run(100).0, Halted::Halted);
+    assert_eq!(cpu.data_mem[5..7], [1, 1]);
+}
+
+#[test]
+fn allow_self_modify() {
+    let source = "loadimm.halt r10, 7\nhalt";
+    let mut blocked = cpu(source);
+    blocked.allow_self_modify = false;
+    assert_eq!(
+        blocked.run(10).0,
+        Halted::Errored(ErrorKind::SelfModifyBlocked(10))
+    );
+    assert_eq!(blocked.write(10, 7), Err(CpuError::ReadOnly(10)));
+    assert_eq!(blocked.read(10), Some(0));
+
+    let mut allowed = cpu(source);
+    assert_eq!(allowed.run(10).0, Halted::Halted);
+    assert_eq!(allowed.read(10), Some(7));
+    assert_eq!(allowed.write(10, 8), Ok(()));
+}
+
+#[test]
+fn banker_bounds() {
+    let mut banker: Banker<[u8; 64]> = Banker::new([0; 64]);
+    assert_eq!(banker.try_get::<u8>(63), Some(0));
+    assert_eq!(banker.try_get::<u8>(64), None);
+    assert_eq!(banker.try_set(64, 1), None);
+    assert_eq!(banker.try_set(63, 1), Some(()));
+    // A bank that does not exist
+    banker.pointer = 256;
+    assert_eq!(banker.try_get::<u8>(0), None);
+}
+
+#[test]
+fn access_errors() {
+    let mut cpu = cpu("halt");
+    assert_eq!(cpu.try_load(200), Err(CpuError::UnmappedDevice(200)));
+    assert_eq!(cpu.try_push(200, 1), Err(CpuError::UnmappedDevice(200)));
+
+    cpu.allow_self_modify = false;
+    assert_eq!(cpu.try_push(1, 1), Err(CpuError::ReadOnly(1)));
+
+    // Only reachable by pointing past the last bank from the host
+    cpu.data_mem.pointer = 256;
+    assert_eq!(cpu.try_load(128), Err(CpuError::OutOfBounds(128)));
+    assert_eq!(cpu.try_push(128, 1), Err(CpuError::OutOfBounds(128)));
+
+    // Instructions carry on, the fault only stops them with the halt flag
+    let copy = Instruction::Copy(false, false, false, false, 200, 128);
+    assert_eq!(
+        execute_triple(copy.to_3bytes()),
+        (Halted::Running, Some(CpuError::UnmappedDevice(200)))
+    );
+    let copy = Instruction::Copy(true, false, false, false, 200, 128);
+    assert_eq!(
+        execute_triple(copy.to_3bytes()),
+        (
+            Halted::Errored(ErrorKind::UnmappedDevice(200)),
+            Some(CpuError::UnmappedDevice(200))
+        )
+    );
+}
+
+#[test]
+fn push_pop_context() {
+    let mut cpu = cpu("
+        loadimm m0, 2
+        bankdata m0
+        pushctx
+        loadimm m0, 9
+        bankdata m0
+        popctx
+        halt
+    ");
+    assert_eq!(cpu.run(10).0, Halted::Halted);
+    assert_eq!(cpu.data_mem.pointer, 2);
+    assert_eq!(cpu.inst_mem.pointer, 0);
+    assert_eq!(cpu.sp, 64);
+    assert_eq!(cpu.read(128), Some(9));
+}
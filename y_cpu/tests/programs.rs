@@ -0,0 +1,182 @@
+use y_cpu::{assembler::assemble, CpuBuilder, ErrorKind, Halted, Instruction, CPU};
+
+/// Counts m0 up to 10
+const COUNTER: &str = "
+    loadimm m0, 0
+    loadimm m1, 10
+loop:
+    inc m0
+    compeq m0, m1
+    halt
+    loadimm m2, loop
+    jmp m2
+";
+
+/// Greatest common divisor of m0 and m1, left in m0
+const GCD: &str = "
+    loadimm m0, 48
+    loadimm m1, 18
+    loadimm m2, 0
+loop:
+    compeq m1, m2
+    halt
+    mod m0, m1
+    swap m0, m1
+    loadimm m3, loop
+    jmp m3
+";
+
+/// Copies m0..m4 to m10..m14 one byte at a time, by incrementing the operands of the copy
+const MEMCPY: &str = "
+    loadimm m0, 3
+    loadimm m1, 1
+    loadimm m2, 4
+    loadimm m3, 1
+    loadimm m4, 5
+    loadimm m20, 5
+    loadimm m22, loop
+loop:
+    copy m0, m10
+    inc r22
+    inc r23
+    dec m20
+    compne m20, m21
+    jmp m22
+    halt
+";
+
+/// Copies m0..m4 to m10..m14 in one go
+const BLOCK_COPY: &str = "
+    loadimm m0, 2
+    loadimm m1, 7
+    loadimm m2, 1
+    loadimm m3, 8
+    loadimm m4, 2
+    loadimm m30, 138
+    loadimm m31, 5
+    blockcopy.halt m0, m30
+    halt
+";
+
+/// Adds the big endian 16-bit values 0x01FF and 0x0001, leaving the sum in m0 and m1
+const ADD16: &str = "
+    loadimm m0, 0x01
+    loadimm m1, 0xFF
+    loadimm m2, 0x00
+    loadimm m3, 0x01
+    add m1, m3
+    addc m0, m2
+    halt
+";
+
+/// Adds m1 to m0 with the carry set beforehand, leaving 5 + 3 + 1 in m0
+const ADD_WITH_CARRY: &str = "
+    loadimm m0, 5
+    loadimm m1, 3
+    sec
+    addc m0, m1
+    halt
+";
+
+/// Runs a program until it halts, which it has to do within `max_steps`
+fn run_program(bytes: [u8; 127], max_steps: usize) -> CPU {
+    let mut cpu = CPU::new(bytes, Vec::new()).unwrap();
+    let (halted, steps) = cpu.run(max_steps);
+    assert_eq!(halted, Halted::Halted, "still running after {steps} steps");
+    cpu
+}
+
+fn assembled(source: &str) -> [u8; 127] {
+    assemble(source).unwrap()
+}
+
+fn data(cpu: &mut CPU, addrs: std::ops::Range<u8>) -> Vec<u8> {
+    addrs.map(|addr| cpu.read(addr).unwrap()).collect()
+}
+
+#[test]
+fn counter() {
+    let mut cpu = run_program(assembled(COUNTER), 100);
+    assert_eq!(cpu.read(128), Some(10));
+    // Halted on the halt right after the comparison
+    assert_eq!(cpu.read(0), Some(12));
+}
+
+#[test]
+fn gcd() {
+    let mut cpu = run_program(assembled(GCD), 100);
+    assert_eq!(data(&mut cpu, 128..130), [6, 0]);
+}
+
+#[test]
+fn memcpy() {
+    let mut cpu = run_program(assembled(MEMCPY), 100);
+    assert_eq!(data(&mut cpu, 128..133), [3, 1, 4, 1, 5]);
+    assert_eq!(data(&mut cpu, 138..143), [3, 1, 4, 1, 5]);
+    assert_eq!(cpu.read(148), Some(0));
+}
+
+#[test]
+fn block_copy() {
+    let mut cpu = run_program(assembled(BLOCK_COPY), 100);
+    assert_eq!(data(&mut cpu, 138..143), [2, 7, 1, 8, 2]);
+}
+
+#[test]
+fn add16() {
+    let mut cpu = run_program(assembled(ADD16), 100);
+    assert_eq!(data(&mut cpu, 128..130), [0x02, 0x00]);
+    assert!(!cpu.carry());
+}
+
+#[test]
+fn add_with_carry() {
+    let mut cpu = run_program(assembled(ADD_WITH_CARRY), 100);
+    assert_eq!(cpu.read(128), Some(9));
+}
+
+#[test]
+fn read_only_code() {
+    let program = assembled("loadimm m0, 7\nloadimm r40, 7\nhalt");
+    let mut cpu = CpuBuilder::new()
+        .instructions(&program)
+        .read_only_code(true)
+        .build()
+        .unwrap();
+    assert_eq!(
+        cpu.run(10),
+        (Halted::Errored(ErrorKind::SelfModifyBlocked(40)), 2)
+    );
+    assert_eq!(cpu.read(128), Some(7));
+    assert_eq!(cpu.read(40), Some(0));
+}
+
+#[test]
+fn cycle_budget() {
+    let program = assembled("inc m0\nmul m1, m1\ninc m0\ninc m0\nhalt");
+    let cost_model = |inst: &Instruction| match inst {
+        Instruction::Mul(..) => 4,
+        _ => 1,
+    };
+
+    let mut cpu = CPU::new(program, Vec::new()).unwrap();
+    cpu.set_cost_model(cost_model);
+    assert_eq!(cpu.run(4), (Halted::Running, 4));
+    assert_eq!(cpu.read(128), Some(3));
+
+    let mut cpu = CPU::new(program, Vec::new()).unwrap();
+    cpu.set_cost_model(cost_model);
+    // The last inc would take the 7th cycle
+    assert_eq!(cpu.run_cycles(6), (Halted::Running, 6));
+    assert_eq!(cpu.read(128), Some(2));
+    assert_eq!(cpu.cycles(), 6);
+}
+
+#[test]
+fn free_instructions() {
+    let program = assembled("loadimm m0, loop\nloop:\njmp m0");
+    let mut cpu = CPU::new(program, Vec::new()).unwrap();
+    cpu.set_cost_model(|_| 0);
+    assert_eq!(cpu.run_cycles(10), (Halted::Running, 10));
+    assert_eq!(cpu.cycles(), 0);
+}
@@ -0,0 +1,49 @@
+#![cfg(feature = "serde")]
+
+use y_cpu::{assembler::assemble, Halted, Instruction, CPU};
+
+/// Counts m0 up to 10
+const COUNTER: &str = "
+    loadimm m0, 0
+    loadimm m1, 10
+    loadimm m2, loop
+loop:
+    inc m0
+    compeq m0, m1
+    halt
+    jmp m2
+";
+
+#[test]
+fn cpu_round_trip() {
+    let mut cpu = CPU::new(assemble(COUNTER).unwrap(), Vec::new()).unwrap();
+    cpu.data_mem.content[3][7] = 42;
+    cpu.run(10);
+
+    let json = serde_json::to_string(&cpu).unwrap();
+    let mut restored: CPU = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.reg_zero, cpu.reg_zero);
+    assert_eq!(restored.flags, cpu.flags);
+    assert_eq!(restored.sp, cpu.sp);
+    assert_eq!(restored.inst_mem.content, cpu.inst_mem.content);
+    assert_eq!(restored.data_mem.content, cpu.data_mem.content);
+    assert_eq!(restored.cycles(), cpu.cycles());
+
+    assert_eq!(restored.run(100).0, Halted::Halted);
+    assert_eq!(restored.read(128), Some(10));
+}
+
+#[test]
+fn instruction_round_trip() {
+    let inst = Instruction::from_3bytes([0b1010_0100, 128, 129]);
+    let json = serde_json::to_string(&inst).unwrap();
+    assert_eq!(serde_json::from_str::<Instruction>(&json).unwrap(), inst);
+}
+
+#[test]
+fn short_bank() {
+    let mut json: serde_json::Value =
+        serde_json::to_value(CPU::new([0; 127], Vec::new()).unwrap()).unwrap();
+    json["data_mem"]["content"][0] = serde_json::json!([1, 2, 3]);
+    assert!(serde_json::from_value::<CPU>(json).is_err());
+}
@@ -0,0 +1,53 @@
+use y_cpu::{assembler::assemble, devices::RngDevice, StateError, CPU};
+
+fn cpu() -> CPU {
+    let program =
+        assemble("loadimm m0, 5\ncopy m0, dp\nloadimm m1, 7\ncopy dev6, m2\nhalt").unwrap();
+    CPU::new(program, vec![Box::new(RngDevice::new(200, 1))]).unwrap()
+}
+
+#[test]
+fn save_state_round_trip() {
+    let mut saved = cpu();
+    saved.run(3);
+    let state = saved.save_state();
+
+    let mut restored = cpu();
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.reg_zero, 9);
+    assert_eq!(restored.data_mem.pointer, 5);
+    assert_eq!(restored.data_mem[1], 7);
+    assert_eq!(restored.save_state(), state);
+    // The device carries on from the same state
+    assert_eq!(restored.try_load(200), saved.try_load(200));
+}
+
+#[test]
+fn truncated_state() {
+    let state = cpu().save_state();
+    let mut cpu = cpu();
+    cpu.run(1);
+    assert_eq!(
+        cpu.load_state(&state[..state.len() - 1]),
+        Err(StateError::Truncated)
+    );
+    // Nothing was restored
+    assert_eq!(cpu.reg_zero, 3);
+}
+
+#[test]
+fn clone() {
+    let mut original = cpu();
+    original.run(1);
+    let mut copy = original.clone();
+    assert_eq!(copy.run(10).1, 4);
+    assert_eq!(copy.data_mem.pointer, 5);
+
+    assert_eq!(original.reg_zero, 3);
+    assert_eq!(original.data_mem.pointer, 0);
+    assert_eq!(original.data_mem[0], 5);
+    original.data_mem.pointer = 5;
+    assert_eq!(original.data_mem[1], 0);
+    // The copy read from its own generator
+    assert_eq!(original.try_load(200), Ok(0));
+}
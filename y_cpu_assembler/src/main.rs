@@ -31,11 +31,7 @@ impl Instruction {
             if part.starts_with(":") {
                 part = part.trim_matches(':');
                 for option in part.split("").filter(|&x| !x.is_empty()).enumerate() {
-                    if option.1 == "1" {
-                        options[option.0] = true;
-                    } else {
-                        options[option.0] = false;
-                    }
+                    options[option.0] = option.1 == "1";
                 }
             } else if part.starts_with("#") {
                 symbols.push(Symbol::Resolved(
@@ -202,7 +198,7 @@ fn main() {
     let mut instruction_list: Vec<Instruction> = Vec::new();
 
     for line in file_string.split("\n").enumerate() {
-        if line.1.starts_with("//") || line.1 == "" {
+        if line.1.starts_with("//") || line.1.is_empty() {
             continue;
         } else if line.1.starts_with("$") {
             instruction_list.push(Instruction::Symbol(Symbol::UnResolved(line.1.to_owned())));
@@ -283,89 +279,7 @@ fn main() {
     }
     let mut output: Vec<u8> = Vec::new();
     for inst in instruction_list {
-        match inst {
-            Instruction::NoOp(o1, o2, o3, o4, sym1, sym2) => output.append(&mut Vec::from([
-                (options_as_u8(o1, o2, o3, o4) | 0b0000_0000),
-                sym1.get_address(),
-                sym2.get_address(),
-            ])),
-            Instruction::And(o1, o2, o3, o4, sym1, sym2) => output.append(&mut Vec::from([
-                (options_as_u8(o1, o2, o3, o4) | 0b0000_0001),
-                sym1.get_address(),
-                sym2.get_address(),
-            ])),
-            Instruction::Or(o1, o2, o3, o4, sym1, sym2) => output.append(&mut Vec::from([
-                (options_as_u8(o1, o2, o3, o4) | 0b0000_0010),
-                sym1.get_address(),
-                sym2.get_address(),
-            ])),
-            Instruction::Not(o1, o2, o3, o4, sym1, sym2) => output.append(&mut Vec::from([
-                (options_as_u8(o1, o2, o3, o4) | 0b0000_0011),
-                sym1.get_address(),
-                sym2.get_address(),
-            ])),
-            Instruction::Add(o1, o2, o3, o4, sym1, sym2) => output.append(&mut Vec::from([
-                (options_as_u8(o1, o2, o3, o4) | 0b0000_0100),
-                sym1.get_address(),
-                sym2.get_address(),
-            ])),
-            Instruction::Sub(o1, o2, o3, o4, sym1, sym2) => output.append(&mut Vec::from([
-                (options_as_u8(o1, o2, o3, o4) | 0b0000_0101),
-                sym1.get_address(),
-                sym2.get_address(),
-            ])),
-            Instruction::Mul(o1, o2, o3, o4, sym1, sym2) => output.append(&mut Vec::from([
-                (options_as_u8(o1, o2, o3, o4) | 0b0000_0110),
-                sym1.get_address(),
-                sym2.get_address(),
-            ])),
-            Instruction::Div(o1, o2, o3, o4, sym1, sym2) => output.append(&mut Vec::from([
-                (options_as_u8(o1, o2, o3, o4) | 0b0000_0111),
-                sym1.get_address(),
-                sym2.get_address(),
-            ])),
-            Instruction::SL(o1, o2, o3, o4, sym1, sym2) => output.append(&mut Vec::from([
-                (options_as_u8(o1, o2, o3, o4) | 0b0000_1000),
-                sym1.get_address(),
-                sym2.get_address(),
-            ])),
-            Instruction::SR(o1, o2, o3, o4, sym1, sym2) => output.append(&mut Vec::from([
-                (options_as_u8(o1, o2, o3, o4) | 0b0000_1001),
-                sym1.get_address(),
-                sym2.get_address(),
-            ])),
-            Instruction::RL(o1, o2, o3, o4, sym1, sym2) => output.append(&mut Vec::from([
-                (options_as_u8(o1, o2, o3, o4) | 0b0000_1010),
-                sym1.get_address(),
-                sym2.get_address(),
-            ])),
-            Instruction::RR(o1, o2, o3, o4, sym1, sym2) => output.append(&mut Vec::from([
-                (options_as_u8(o1, o2, o3, o4) | 0b0000_1011),
-                sym1.get_address(),
-                sym2.get_address(),
-            ])),
-            Instruction::Copy(o1, o2, o3, o4, sym1, sym2) => output.append(&mut Vec::from([
-                (options_as_u8(o1, o2, o3, o4) | 0b0000_1100),
-                sym1.get_address(),
-                sym2.get_address(),
-            ])),
-            Instruction::CompEq(o1, o2, o3, o4, sym1, sym2) => output.append(&mut Vec::from([
-                (options_as_u8(o1, o2, o3, o4) | 0b0000_1101),
-                sym1.get_address(),
-                sym2.get_address(),
-            ])),
-            Instruction::CompGt(o1, o2, o3, o4, sym1, sym2) => output.append(&mut Vec::from([
-                (options_as_u8(o1, o2, o3, o4) | 0b0000_1110),
-                sym1.get_address(),
-                sym2.get_address(),
-            ])),
-            Instruction::CompLt(o1, o2, o3, o4, sym1, sym2) => output.append(&mut Vec::from([
-                (options_as_u8(o1, o2, o3, o4) | 0b0000_1111),
-                sym1.get_address(),
-                sym2.get_address(),
-            ])),
-            Instruction::Symbol(_) => continue,
-        }
+        output.append(&mut encode(inst));
     }
     fs::write(
         format!("./{}", input_path[1].replace(".ysm", ".bin")),
@@ -374,19 +288,106 @@ fn main() {
     .unwrap();
 }
 
+/// The bytes of an instruction, symbols take none
+fn encode(inst: Instruction) -> Vec<u8> {
+    match inst {
+        Instruction::NoOp(o1, o2, o3, o4, sym1, sym2) => Vec::from([
+            options_as_u8(o1, o2, o3, o4),
+            sym1.get_address(),
+            sym2.get_address(),
+        ]),
+        Instruction::And(o1, o2, _, _, sym1, sym2) => Vec::from([
+            (options_as_u8(o1, o2, false, false) | 0b0000_0001),
+            sym1.get_address(),
+            sym2.get_address(),
+        ]),
+        Instruction::Or(o1, o2, _, _, sym1, sym2) => Vec::from([
+            (options_as_u8(o1, o2, false, false) | 0b0000_0010),
+            sym1.get_address(),
+            sym2.get_address(),
+        ]),
+        Instruction::Not(o1, o2, o3, o4, sym1, sym2) => Vec::from([
+            (options_as_u8(o1, o2, o3, o4) | 0b0000_0011),
+            sym1.get_address(),
+            sym2.get_address(),
+        ]),
+        Instruction::Add(o1, o2, o3, o4, sym1, sym2) => Vec::from([
+            (options_as_u8(o1, o2, o3, o4) | 0b0000_0100),
+            sym1.get_address(),
+            sym2.get_address(),
+        ]),
+        Instruction::Sub(o1, o2, o3, o4, sym1, sym2) => Vec::from([
+            (options_as_u8(o1, o2, o3, o4) | 0b0000_0101),
+            sym1.get_address(),
+            sym2.get_address(),
+        ]),
+        Instruction::Mul(o1, o2, o3, o4, sym1, sym2) => Vec::from([
+            (options_as_u8(o1, o2, o3, o4) | 0b0000_0110),
+            sym1.get_address(),
+            sym2.get_address(),
+        ]),
+        Instruction::Div(o1, o2, o3, o4, sym1, sym2) => Vec::from([
+            (options_as_u8(o1, o2, o3, o4) | 0b0000_0111),
+            sym1.get_address(),
+            sym2.get_address(),
+        ]),
+        Instruction::SL(o1, o2, _, _, sym1, sym2) => Vec::from([
+            (options_as_u8(o1, o2, false, false) | 0b0000_1000),
+            sym1.get_address(),
+            sym2.get_address(),
+        ]),
+        Instruction::SR(o1, o2, _, _, sym1, sym2) => Vec::from([
+            (options_as_u8(o1, o2, false, true) | 0b0000_1000),
+            sym1.get_address(),
+            sym2.get_address(),
+        ]),
+        Instruction::RL(o1, o2, _, _, sym1, sym2) => Vec::from([
+            (options_as_u8(o1, o2, true, false) | 0b0000_1000),
+            sym1.get_address(),
+            sym2.get_address(),
+        ]),
+        Instruction::RR(o1, o2, _, _, sym1, sym2) => Vec::from([
+            (options_as_u8(o1, o2, true, true) | 0b0000_1000),
+            sym1.get_address(),
+            sym2.get_address(),
+        ]),
+        Instruction::Copy(o1, o2, _, _, sym1, sym2) => Vec::from([
+            (options_as_u8(o1, o2, false, false) | 0b0000_1100),
+            sym1.get_address(),
+            sym2.get_address(),
+        ]),
+        Instruction::CompEq(o1, o2, _, _, sym1, sym2) => Vec::from([
+            (options_as_u8(o1, o2, false, false) | 0b0000_1101),
+            sym1.get_address(),
+            sym2.get_address(),
+        ]),
+        Instruction::CompGt(o1, o2, o3, o4, sym1, sym2) => Vec::from([
+            (options_as_u8(o1, o2, o3, o4) | 0b0000_1110),
+            sym1.get_address(),
+            sym2.get_address(),
+        ]),
+        Instruction::CompLt(o1, o2, o3, o4, sym1, sym2) => Vec::from([
+            (options_as_u8(o1, o2, o3, o4) | 0b0000_1111),
+            sym1.get_address(),
+            sym2.get_address(),
+        ]),
+        Instruction::Symbol(_) => Vec::new(),
+    }
+}
+
 pub fn resolve_symbols(
     symbol_ref_list: &HashMap<&String, i32>,
     sym1: &mut Symbol,
     sym2: &mut Symbol,
 ) {
     match sym1 {
-        Symbol::Resolved(_) => return,
+        Symbol::Resolved(_) => {}
         Symbol::UnResolved(name) => {
             *sym1 = Symbol::Resolved(symbol_ref_list[name].try_into().unwrap())
         }
     }
     match sym2 {
-        Symbol::Resolved(_) => return,
+        Symbol::Resolved(_) => {}
         Symbol::UnResolved(name) => {
             *sym2 = Symbol::Resolved(symbol_ref_list[name].try_into().unwrap())
         }
@@ -404,3 +405,22 @@ pub fn options_as_u8(
         | ((arg1_signed as u8) << 5)
         | ((arg2_signed as u8) << 4)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn or_ignores_sign_bits() {
+        // The sign bits of opcode 0010 select Xor and Nor
+        let inst = Instruction::from_text("OR :1011 #80 #81").unwrap();
+        assert_eq!(encode(inst), [0b1000_0010, 0x80, 0x81]);
+    }
+
+    #[test]
+    fn copy_ignores_sign_bits() {
+        // The sign bits of opcode 1100 select LoadImm, Swap and BlockCopy
+        let inst = Instruction::from_text("COPY :0111 #80 #81").unwrap();
+        assert_eq!(encode(inst), [0b0100_1100, 0x80, 0x81]);
+    }
+}
@@ -1,17 +1,14 @@
 use std::{
-    fs::File,
+    fs,
     io::{self, Read},
 };
 
-use y_cpu::CPU;
+use y_cpu::{loader::load_binary, CPU};
 
 fn main() {
-    let mut file = File::open("example.bin").unwrap();
-    let mut binary = [0_u8; 127];
+    let binary = load_binary(&fs::read("example.bin").unwrap()).unwrap();
 
-    file.read(&mut binary).unwrap();
-
-    let mut cpu = CPU::new(binary, Vec::new());
+    let mut cpu = CPU::new(binary, Vec::new()).unwrap();
 
     loop {
         println!("Next Instruction: {:?}", cpu.fetch());
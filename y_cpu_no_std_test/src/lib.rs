@@ -0,0 +1,36 @@
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use y_cpu::{assembler::assemble, Halted, CPU};
+
+/// Counts m0 up to 10
+const COUNTER: &str = "
+    loadimm m0, 0
+    loadimm m1, 10
+    loadimm m2, loop
+loop:
+    inc m0
+    compeq m0, m1
+    halt
+    jmp m2
+";
+
+/// Assembles and runs the counter program, returning the final count if it halted in time
+pub fn count() -> Option<u8> {
+    let mut cpu = CPU::new(assemble(COUNTER).ok()?, Vec::new()).ok()?;
+    match cpu.run(100) {
+        (Halted::Halted, _) => cpu.read(128),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn count() {
+        assert_eq!(super::count(), Some(10));
+    }
+}